@@ -1,6 +1,9 @@
 use std::io::Result;
 
 fn main() -> Result<()> {
+    #[cfg(feature = "generator")]
     prost_build::compile_protos(&["src/descriptor.proto"], &["src/"])?;
+    #[cfg(feature = "grpc")]
+    tonic_prost_build::compile_protos("src/service.proto")?;
     Ok(())
 }
@@ -0,0 +1,295 @@
+use crate::{
+    base64_serde, Descriptor, Error, Filter, Manifest, PublicKeyManifest, Result, FILTTER_VERSION,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+/// The name of the content index in a bundle archive
+pub const BUNDLE_INDEX: &str = "index.json";
+
+/// The artifacts that make up a bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Artifact {
+    Descriptor,
+    Data,
+    Manifest,
+    PublicKey,
+    Filter,
+}
+
+impl Artifact {
+    pub const ALL: [Artifact; 5] = [
+        Self::Descriptor,
+        Self::Data,
+        Self::Manifest,
+        Self::PublicKey,
+        Self::Filter,
+    ];
+
+    /// The file name of the artifact in a bundle archive
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::Descriptor => "descriptor.bin.gz",
+            Self::Data => "data.bin",
+            Self::Manifest => "manifest.json",
+            Self::PublicKey => "public_key.json",
+            Self::Filter => "filter.bin",
+        }
+    }
+
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|artifact| artifact.file_name() == name)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleIndex {
+    pub serial: u32,
+    pub files: Vec<BundleEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub name: String,
+    pub size: u64,
+    #[serde(with = "base64_serde")]
+    pub sha256: Vec<u8>,
+}
+
+impl BundleEntry {
+    fn new(artifact: Artifact, data: &[u8]) -> Self {
+        Self {
+            name: artifact.file_name().to_string(),
+            size: data.len() as u64,
+            sha256: Sha256::digest(data).to_vec(),
+        }
+    }
+}
+
+/// A single archive holding the descriptor, signing data, manifest, public
+/// key and filter for one filter serial.
+pub struct Bundle {
+    pub index: BundleIndex,
+    files: HashMap<Artifact, Vec<u8>>,
+}
+
+impl Bundle {
+    /// Constructs a bundle from the contents of every artifact. The serial of
+    /// the bundle is taken from the signing data.
+    pub fn new(files: HashMap<Artifact, Vec<u8>>) -> Result<Self> {
+        for artifact in Artifact::ALL {
+            if !files.contains_key(&artifact) {
+                return Err(Error::bundle(&format!("missing {}", artifact.file_name())));
+            }
+        }
+        let data = Filter::from_signing_bytes(&files[&Artifact::Data], FILTTER_VERSION)?;
+        let index = BundleIndex {
+            serial: data.serial,
+            files: Artifact::ALL
+                .iter()
+                .map(|artifact| BundleEntry::new(*artifact, &files[artifact]))
+                .collect(),
+        };
+        Ok(Self { index, files })
+    }
+
+    pub fn get(&self, artifact: Artifact) -> Option<&[u8]> {
+        self.files.get(&artifact).map(Vec::as_slice)
+    }
+
+    pub fn serial(&self) -> u32 {
+        self.index.serial
+    }
+
+    /// Reads a bundle from a tar archive. Entries that are not the index or a
+    /// known artifact, and duplicate entries, are rejected.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut archive = tar::Archive::new(reader);
+        let mut index: Option<BundleIndex> = None;
+        let mut files = HashMap::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().to_string();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            if name == BUNDLE_INDEX {
+                if index.is_some() {
+                    return Err(Error::bundle(&format!("duplicate entry {name}")));
+                }
+                index = Some(serde_json::from_slice(&data)?);
+            } else if let Some(artifact) = Artifact::from_file_name(&name) {
+                if files.insert(artifact, data).is_some() {
+                    return Err(Error::bundle(&format!("duplicate entry {name}")));
+                }
+            } else {
+                return Err(Error::bundle(&format!("unexpected entry {name}")));
+            }
+        }
+        let index = index.ok_or_else(|| Error::bundle("missing index"))?;
+        Ok(Self { index, files })
+    }
+
+    /// Writes the bundle as a tar archive with the index as the first entry.
+    /// Entry metadata is fixed so the same artifacts always produce the same
+    /// archive.
+    pub fn to_writer<W: Write>(&self, writer: W) -> Result<()> {
+        let mut builder = tar::Builder::new(writer);
+        let index = serde_json::to_vec_pretty(&self.index)?;
+        append_entry(&mut builder, BUNDLE_INDEX, &index)?;
+        for entry in &self.index.files {
+            if let Some(data) = Artifact::from_file_name(&entry.name).and_then(|a| self.get(a)) {
+                append_entry(&mut builder, &entry.name, data)?;
+            }
+        }
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+
+    /// Checks the bundle contents against the index and the artifacts against
+    /// each other.
+    pub fn verify(&self) -> BundleVerify {
+        let mut checks = vec![BundleCheck::new("index", self.verify_index())];
+        match self.verify_data() {
+            Ok(data) => {
+                checks.push(BundleCheck::new(
+                    "descriptor",
+                    self.verify_descriptor(&data),
+                ));
+                checks.push(BundleCheck::new("manifest", self.verify_manifest(&data)));
+                checks.push(BundleCheck::new("filter", self.verify_filter(&data)));
+            }
+            Err(err) => checks.push(BundleCheck::new("data", Err(err))),
+        }
+        BundleVerify {
+            serial: self.serial(),
+            checks,
+        }
+    }
+
+    fn artifact(&self, artifact: Artifact) -> Result<&[u8]> {
+        self.get(artifact)
+            .ok_or_else(|| Error::bundle(&format!("missing {}", artifact.file_name())))
+    }
+
+    fn verify_index(&self) -> Result<()> {
+        for artifact in Artifact::ALL {
+            let data = self.artifact(artifact)?;
+            let entry = self
+                .index
+                .files
+                .iter()
+                .find(|entry| entry.name == artifact.file_name())
+                .ok_or_else(|| Error::bundle(&format!("{} not in index", artifact.file_name())))?;
+            if entry.size != data.len() as u64 || entry.sha256 != Sha256::digest(data).to_vec() {
+                return Err(Error::bundle(&format!(
+                    "{} does not match index",
+                    artifact.file_name()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_data(&self) -> Result<Filter> {
+        let data = Filter::from_signing_bytes(self.artifact(Artifact::Data)?, FILTTER_VERSION)?;
+        if data.serial != self.serial() {
+            return Err(Error::bundle(&format!(
+                "data serial {} does not match bundle serial {}",
+                data.serial,
+                self.serial()
+            )));
+        }
+        Ok(data)
+    }
+
+    fn verify_descriptor(&self, data: &Filter) -> Result<()> {
+        let descriptor = Descriptor::from_reader(self.artifact(Artifact::Descriptor)?)?;
+        let generated =
+            Filter::from_descriptor_with_kind(data.serial, &descriptor, (&data.filter).into())?;
+        if generated.to_signing_bytes()? != self.artifact(Artifact::Data)? {
+            return Err(Error::bundle("descriptor does not reproduce data"));
+        }
+        Ok(())
+    }
+
+    fn verify_manifest(&self, data: &Filter) -> Result<()> {
+        let manifest: Manifest = serde_json::from_slice(self.artifact(Artifact::Manifest)?)?;
+        if manifest.serial != data.serial {
+            return Err(Error::bundle(&format!(
+                "manifest serial {} does not match data serial {}",
+                manifest.serial, data.serial
+            )));
+        }
+        if base64_serde::decode(&manifest.hash)? != data.hash()? {
+            return Err(Error::bundle("data hash does not match manifest hash"));
+        }
+        Ok(())
+    }
+
+    fn verify_filter(&self, data: &Filter) -> Result<()> {
+        let key_manifest: PublicKeyManifest =
+            serde_json::from_slice(self.artifact(Artifact::PublicKey)?)?;
+        key_manifest.validate_policy()?;
+        let filter = Filter::from_bytes(self.artifact(Artifact::Filter)?)?;
+        if filter.to_signing_bytes()? != data.to_signing_bytes()? {
+            return Err(Error::bundle("filter does not match data"));
+        }
+        filter.verify_with_policy(&key_manifest)?;
+        Ok(())
+    }
+}
+
+fn append_entry<W: Write>(builder: &mut tar::Builder<W>, name: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct BundleVerify {
+    pub serial: u32,
+    pub checks: Vec<BundleCheck>,
+}
+
+impl BundleVerify {
+    pub fn is_verified(&self) -> bool {
+        self.checks.iter().all(|check| check.verified)
+    }
+
+    pub fn failed(&self) -> Vec<&'static str> {
+        self.checks
+            .iter()
+            .filter(|check| !check.verified)
+            .map(|check| check.check)
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BundleCheck {
+    pub check: &'static str,
+    pub verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BundleCheck {
+    fn new(check: &'static str, result: Result<()>) -> Self {
+        Self {
+            check,
+            verified: result.is_ok(),
+            error: result.err().map(|err| err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::cmd::{error::Error, open_input_file, open_output_file, print_json, read_input_file};
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use xorf_generator::{Artifact, Bundle};
+
+#[derive(clap::Args, Debug)]
+pub struct Cmd {
+    #[command(subcommand)]
+    pub cmd: BundleCommand,
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result<()> {
+        self.cmd.run()
+    }
+}
+
+/// Commands on bundle archives
+#[derive(clap::Subcommand, Debug)]
+pub enum BundleCommand {
+    Pack(Pack),
+    Unpack(Unpack),
+    Verify(Verify),
+}
+
+impl BundleCommand {
+    pub fn run(&self) -> Result<()> {
+        match self {
+            Self::Pack(cmd) => cmd.run(),
+            Self::Unpack(cmd) => cmd.run(),
+            Self::Verify(cmd) => cmd.run(),
+        }
+    }
+}
+
+/// Pack the descriptor, signing data, manifest, public key and filter into a
+/// single bundle archive
+///
+/// The archive includes an index with the size and hash of every artifact.
+#[derive(Debug, clap::Args)]
+pub struct Pack {
+    /// The descriptor file to include
+    #[arg(long, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The signing data file to include
+    #[arg(long, short, default_value = "data.bin")]
+    data: PathBuf,
+    /// The manifest file to include
+    #[arg(long, short, default_value = "manifest.json")]
+    manifest: PathBuf,
+    /// The public key file to include
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+    /// The filter file to include
+    #[arg(long, short, default_value = "filter.bin")]
+    filter: PathBuf,
+    /// The file to write the bundle to. Defaults to bundle-<serial>.tar
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+impl Pack {
+    pub fn run(&self) -> Result<()> {
+        let mut files = HashMap::new();
+        for (artifact, path) in [
+            (Artifact::Descriptor, &self.descriptor),
+            (Artifact::Data, &self.data),
+            (Artifact::Manifest, &self.manifest),
+            (Artifact::PublicKey, &self.key),
+            (Artifact::Filter, &self.filter),
+        ] {
+            files.insert(artifact, read_input_file(path)?);
+        }
+        let bundle = Bundle::new(files)?;
+        let verify = bundle.verify();
+        if !verify.is_verified() {
+            print_json(&verify)?;
+            return Err(Error::bundle_invalid(&verify.failed()).into());
+        }
+
+        let output = self
+            .output
+            .clone()
+            .unwrap_or_else(|| format!("bundle-{}.tar", bundle.serial()).into());
+        let mut file = open_output_file(&output, false)?;
+        bundle.to_writer(&mut file)?;
+        file.flush()?;
+        print_json(&json!({
+            "bundle": output,
+            "index": bundle.index,
+        }))
+    }
+}
+
+/// Unpack the artifacts in a bundle archive into a directory
+#[derive(Debug, clap::Args)]
+pub struct Unpack {
+    /// The bundle to unpack
+    input: PathBuf,
+    /// The directory to unpack the artifacts into
+    #[arg(long, short, default_value = ".")]
+    dir: PathBuf,
+    /// Whether to force overwrite existing files
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl Unpack {
+    pub fn run(&self) -> Result<()> {
+        let bundle = read_bundle(&self.input)?;
+        fs::create_dir_all(&self.dir)
+            .context(format!("creating directory {}", self.dir.display()))?;
+        let mut files = vec![];
+        for artifact in Artifact::ALL {
+            if let Some(data) = bundle.get(artifact) {
+                let path = self.dir.join(artifact.file_name());
+                let mut file = open_output_file(&path, !self.force)?;
+                file.write_all(data)?;
+                files.push(path);
+            }
+        }
+        print_json(&json!({
+            "serial": bundle.serial(),
+            "files": files,
+        }))
+    }
+}
+
+/// Verify a bundle archive
+///
+/// This checks every artifact against the hashes in the bundle index, that
+/// the descriptor reproduces the signing data, that the signing data matches
+/// the manifest hash and that the filter verifies against the public key.
+#[derive(Debug, clap::Args)]
+pub struct Verify {
+    /// The bundle to verify
+    input: PathBuf,
+}
+
+impl Verify {
+    pub fn run(&self) -> Result<()> {
+        let bundle = read_bundle(&self.input)?;
+        let verify = bundle.verify();
+        print_json(&verify)?;
+        if !verify.is_verified() {
+            return Err(Error::bundle_invalid(&verify.failed()).into());
+        }
+        Ok(())
+    }
+}
+
+fn read_bundle(path: &Path) -> Result<Bundle> {
+    Bundle::from_reader(std::io::BufReader::new(open_input_file(path)?))
+        .context(format!("reading bundle {}", path.display()))
+}
@@ -0,0 +1,305 @@
+use crate::cmd::print_json;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+use xorf_generator::Artifact;
+
+/// The configuration file that is used when it is in the working directory
+pub const CONFIG_FILE: &str = "xorf-generator.toml";
+
+/// A configuration file with the default paths and options for commands.
+///
+/// The top level settings apply to every profile and the settings of the
+/// selected profile override them. Relative paths are resolved against the
+/// directory of the configuration file and the `dir` of the settings.
+#[derive(Debug, Serialize)]
+pub struct Config {
+    file: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    key: PathBuf,
+    descriptor: PathBuf,
+    data: PathBuf,
+    manifest: PathBuf,
+    filter: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    network: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<String>,
+}
+
+/// The settings of a configuration file or one of its profiles
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    /// The directory with the files of the profile
+    dir: Option<PathBuf>,
+    key: Option<PathBuf>,
+    descriptor: Option<PathBuf>,
+    data: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+    filter: Option<PathBuf>,
+    network: Option<String>,
+    kind: Option<String>,
+    compression: Option<String>,
+}
+
+impl Settings {
+    /// Returns these settings with the given settings taking precedence
+    fn merge(self, other: Self) -> Self {
+        Self {
+            dir: other.dir.or(self.dir),
+            key: other.key.or(self.key),
+            descriptor: other.descriptor.or(self.descriptor),
+            data: other.data.or(self.data),
+            manifest: other.manifest.or(self.manifest),
+            filter: other.filter.or(self.filter),
+            network: other.network.or(self.network),
+            kind: other.kind.or(self.kind),
+            compression: other.compression.or(self.compression),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file and profile given in the command line
+    /// arguments, or the configuration file in the working directory if there
+    /// is one.
+    pub fn from_args(args: &[OsString]) -> Result<Option<Self>> {
+        let profile =
+            option_value(args, "--profile").map(|profile| profile.to_string_lossy().into_owned());
+        let path = match option_value(args, "--config") {
+            Some(path) => PathBuf::from(path),
+            None if Path::new(CONFIG_FILE).exists() => PathBuf::from(CONFIG_FILE),
+            None => match profile {
+                Some(profile) => anyhow::bail!("no {CONFIG_FILE} to select profile {profile} from"),
+                None => return Ok(None),
+            },
+        };
+        let data =
+            fs::read_to_string(&path).context(format!("reading config {}", path.display()))?;
+        Self::from_str(&path, &data, profile.as_deref())
+            .context(format!("reading config {}", path.display()))
+            .map(Some)
+    }
+
+    fn from_str(path: &Path, data: &str, profile: Option<&str>) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(data)?;
+        let default_profile: Option<String> = table
+            .remove("profile")
+            .map(|value| value.try_into())
+            .transpose()?;
+        let mut profiles: BTreeMap<String, Settings> = table
+            .remove("profiles")
+            .map(|value| value.try_into())
+            .transpose()?
+            .unwrap_or_default();
+        let mut settings: Settings = toml::Value::Table(table).try_into()?;
+
+        let profile = profile.map(str::to_string).or(default_profile);
+        if let Some(name) = &profile {
+            let overrides = profiles.remove(name).ok_or_else(|| {
+                let names = profiles.keys().cloned().collect::<Vec<_>>();
+                anyhow::anyhow!(
+                    "unknown profile {name}, expected one of {}",
+                    names.join(", ")
+                )
+            })?;
+            settings = settings.merge(overrides);
+        }
+
+        let mut base = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        if let Some(dir) = &settings.dir {
+            base = base.join(dir);
+        }
+        let resolve = |path: Option<PathBuf>, artifact: Artifact| {
+            base.join(path.unwrap_or_else(|| artifact.file_name().into()))
+        };
+        Ok(Self {
+            key: resolve(settings.key, Artifact::PublicKey),
+            descriptor: resolve(settings.descriptor, Artifact::Descriptor),
+            data: resolve(settings.data, Artifact::Data),
+            manifest: resolve(settings.manifest, Artifact::Manifest),
+            filter: resolve(settings.filter, Artifact::Filter),
+            network: settings.network,
+            kind: settings.kind,
+            compression: settings.compression,
+            file: path.to_path_buf(),
+            profile,
+        })
+    }
+
+    fn path(&self, artifact: Artifact) -> &Path {
+        match artifact {
+            Artifact::PublicKey => &self.key,
+            Artifact::Descriptor => &self.descriptor,
+            Artifact::Data => &self.data,
+            Artifact::Manifest => &self.manifest,
+            Artifact::Filter => &self.filter,
+        }
+    }
+
+    /// Replaces the defaults of the file arguments and options of the given
+    /// command and all of its subcommands with the configured ones
+    pub fn apply(&self, command: clap::Command) -> clap::Command {
+        self.apply_to("", command)
+    }
+
+    /// Applies the configuration to the command with the given path of
+    /// subcommand names below the top level command
+    fn apply_to(&self, path: &str, command: clap::Command) -> clap::Command {
+        let subcommands = command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect::<Vec<_>>();
+        let mut command = command.mut_args(|arg| {
+            let id = arg.get_id().as_str();
+            let option = match id {
+                "network" => self.network.clone(),
+                "kind" => self.kind.clone(),
+                "compression" => self.compression.clone(),
+                _ => None,
+            };
+            if let Some(value) = option {
+                return arg.default_value(value);
+            }
+            match file_arg(path, id) {
+                Some(artifact) => {
+                    arg.default_value(self.path(artifact).to_string_lossy().into_owned())
+                }
+                None => arg,
+            }
+        });
+        for name in subcommands {
+            let path = match path {
+                "" => name.clone(),
+                path => format!("{path} {name}"),
+            };
+            command = command.mut_subcommand(name, |subcommand| self.apply_to(&path, subcommand));
+        }
+        command
+    }
+}
+
+/// The file arguments that default to a configured path, by subcommand path
+/// and argument id
+const FILE_ARGS: &[(&str, &str, Artifact)] = &[
+    ("bundle pack", "descriptor", Artifact::Descriptor),
+    ("bundle pack", "data", Artifact::Data),
+    ("bundle pack", "manifest", Artifact::Manifest),
+    ("bundle pack", "key", Artifact::PublicKey),
+    ("bundle pack", "filter", Artifact::Filter),
+    ("descriptor generate", "output", Artifact::Descriptor),
+    ("descriptor count-edges", "input", Artifact::Descriptor),
+    ("descriptor find", "input", Artifact::Descriptor),
+    ("descriptor info", "input", Artifact::Descriptor),
+    ("descriptor stats", "input", Artifact::Descriptor),
+    ("descriptor graph", "input", Artifact::Descriptor),
+    ("data generate", "input", Artifact::Descriptor),
+    ("data generate", "output", Artifact::Data),
+    ("filter generate", "data", Artifact::Data),
+    ("filter generate", "key", Artifact::PublicKey),
+    ("filter generate", "output", Artifact::Filter),
+    ("filter generate", "manifest", Artifact::Manifest),
+    ("filter contains", "input", Artifact::Filter),
+    ("filter verify", "input", Artifact::Filter),
+    ("filter verify", "key", Artifact::PublicKey),
+    ("filter info", "input", Artifact::Filter),
+    ("key info", "input", Artifact::PublicKey),
+    ("key rotate", "key", Artifact::PublicKey),
+    ("key verify-chain", "key", Artifact::PublicKey),
+    ("manifest generate", "data", Artifact::Data),
+    ("manifest generate", "key", Artifact::PublicKey),
+    ("manifest generate", "manifest", Artifact::Manifest),
+    ("manifest verify", "data", Artifact::Data),
+    ("manifest verify", "key", Artifact::PublicKey),
+    ("manifest verify", "manifest", Artifact::Manifest),
+    ("manifest collect", "data", Artifact::Data),
+    ("manifest collect", "key", Artifact::PublicKey),
+    ("manifest collect", "manifest", Artifact::Manifest),
+    ("manifest attest", "data", Artifact::Data),
+    ("manifest attest", "manifest", Artifact::Manifest),
+    ("manifest attest", "descriptor", Artifact::Descriptor),
+    ("manifest envelope", "data", Artifact::Data),
+    ("manifest envelope", "manifest", Artifact::Manifest),
+    ("manifest sign", "data", Artifact::Data),
+    ("manifest sign", "key", Artifact::PublicKey),
+    ("manifest sign", "manifest", Artifact::Manifest),
+    ("serve", "input", Artifact::Filter),
+    ("serve", "key", Artifact::PublicKey),
+];
+
+fn file_arg(path: &str, id: &str) -> Option<Artifact> {
+    FILE_ARGS
+        .iter()
+        .find(|(arg_path, arg_id, _)| *arg_path == path && *arg_id == id)
+        .map(|(_, _, artifact)| *artifact)
+}
+
+/// Finds the value of a top level option in the command line arguments. This
+/// is used to load the configuration before the arguments are parsed with its
+/// defaults.
+pub fn option_value(args: &[OsString], name: &str) -> Option<OsString> {
+    let mut args = args.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix(name)?.strip_prefix('='))
+        {
+            return Some(value.into());
+        }
+    }
+    None
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Cmd {
+    #[command(subcommand)]
+    pub cmd: ConfigCommand,
+}
+
+impl Cmd {
+    pub fn run(&self, config: Option<&Config>) -> Result<()> {
+        self.cmd.run(config)
+    }
+}
+
+/// Commands on the configuration file
+#[derive(clap::Subcommand, Debug)]
+pub enum ConfigCommand {
+    Show(Show),
+}
+
+impl ConfigCommand {
+    pub fn run(&self, config: Option<&Config>) -> Result<()> {
+        match self {
+            Self::Show(cmd) => cmd.run(config),
+        }
+    }
+}
+
+/// Print the configuration file and profile in use, with the resolved file
+/// paths and options
+#[derive(clap::Args, Debug)]
+pub struct Show {}
+
+impl Show {
+    pub fn run(&self, config: Option<&Config>) -> Result<()> {
+        match config {
+            Some(config) => print_json(config),
+            None => {
+                anyhow::bail!("no {CONFIG_FILE} in the working directory and no --config given")
+            }
+        }
+    }
+}
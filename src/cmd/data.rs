@@ -1,7 +1,7 @@
-use crate::cmd::open_output_file;
+use crate::cmd::{open_input_file, open_output_file};
 use anyhow::{Context, Result};
 use std::{io::Write, path::PathBuf};
-use xorf_generator::{Descriptor, Filter};
+use xorf_generator::{Descriptor, Filter, FilterKind};
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
@@ -41,14 +41,17 @@ pub struct Generate {
     /// The serial number embedded in the signing bytes
     #[arg(long, short)]
     serial: u32,
+    /// The kind of filter to generate: xor or binary_fuse
+    #[arg(long, default_value = "xor")]
+    kind: FilterKind,
 }
 
 impl Generate {
     pub fn run(&self) -> Result<()> {
         let mut data_file = open_output_file(&self.output, false)?;
-        let descriptor = Descriptor::from_path(&self.input)
+        let descriptor = Descriptor::from_reader(open_input_file(&self.input)?)
             .context(format!("reading descriptor {}", self.input.display()))?;
-        let filter = Filter::from_descriptor(self.serial, &descriptor)?;
+        let filter = Filter::from_descriptor_with_kind(self.serial, &descriptor, self.kind)?;
         let signing_bytes = filter.to_signing_bytes()?;
         data_file.write_all(&signing_bytes)?;
         Ok(())
@@ -1,9 +1,12 @@
-use crate::cmd::{open_output_file, print_json};
+use crate::cmd::{open_input_file, open_output_file, print_json};
 use anyhow::{Context, Result};
 use helium_crypto::{PublicKey, PublicKeyBinary};
 use serde_json::json;
-use std::path::PathBuf;
-use xorf_generator::Descriptor;
+use std::{path::PathBuf, str::FromStr};
+use xorf_generator::{
+    Descriptor, DescriptorBuilder, DescriptorCompression, DescriptorGraph, DescriptorStats,
+    PromotionRule, ReasonCodes,
+};
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
@@ -24,6 +27,8 @@ pub enum DescriptorCommand {
     CountEdges(CountEdges),
     Find(Box<Find>),
     Info(Info),
+    Stats(Stats),
+    Graph(Graph),
 }
 
 impl DescriptorCommand {
@@ -33,26 +38,137 @@ impl DescriptorCommand {
             Self::CountEdges(cmd) => cmd.run(),
             Self::Find(cmd) => cmd.run(),
             Self::Info(cmd) => cmd.run(),
+            Self::Stats(cmd) => cmd.run(),
+            Self::Graph(cmd) => cmd.run(),
         }
     }
 }
 
-/// Generate a descriptor file for the given csv file
+/// Generate a descriptor file for the given csv files
+///
+/// Every csv source is named, either explicitly as `name=path` or by the file
+/// name of the csv file, and every node and edge in the descriptor records
+/// the source it was taken from. When sources disagree on the reason or
+/// carryover for a key or edge the source with the highest precedence wins.
+/// Keys and edges in the allow override files are left out of the descriptor.
+/// Edge keys with many edges can be promoted to full nodes, which prunes
+/// their edges.
+/// Prints a report of the sources, any conflicts between them, the removed
+/// keys and edges and the promoted keys.
 #[derive(Debug, clap::Args)]
 pub struct Generate {
     /// The input csv file to generate a descriptor for
-    input: PathBuf,
+    input: CsvSource,
     /// The file to write the resulting descriptor file to
     #[arg(default_value = "descriptor.bin.gz")]
     output: PathBuf,
+    /// Additional csv sources to merge into the descriptor
+    #[arg(long = "source", short)]
+    sources: Vec<CsvSource>,
+    /// Comma separated source names in order of decreasing precedence.
+    /// Unlisted sources follow in the order they are given
+    #[arg(long, value_delimiter = ',')]
+    precedence: Vec<String>,
+    /// Csv files of public keys or edges to leave out of the descriptor, each
+    /// with the reason for the removal
+    #[arg(long, short)]
+    allow_override: Vec<PathBuf>,
+    /// The reason code registry to check reasons against. Reasons are stored
+    /// as reason code ids when given
+    #[arg(long, short)]
+    reasons: Option<PathBuf>,
+    /// Promote edge keys with more than this number of edges to full nodes
+    #[arg(long)]
+    promote_edges: Option<usize>,
+    /// Promote edge keys whose edges make up at least this fraction of their
+    /// observed peers to full nodes
+    #[arg(long, requires = "observed_peers")]
+    promote_fraction: Option<f64>,
+    /// A csv file with the number of observed peers of public keys
+    #[arg(long)]
+    observed_peers: Option<PathBuf>,
+    /// The reason for promoted full nodes
+    #[arg(long, default_value = "promoted")]
+    promote_reason: String,
+    /// The filter serial the descriptor is intended for. Generating signing
+    /// data for a different serial from the descriptor fails
+    #[arg(long)]
+    serial: Option<u32>,
+    /// The compression of the descriptor file: none, gzip or zstd, optionally
+    /// with a level as in gzip:6 or zstd:19
+    #[arg(long, short, default_value = "gzip")]
+    compression: DescriptorCompression,
 }
 
 impl Generate {
     pub fn run(&self) -> Result<()> {
-        let descriptor = Descriptor::from_csv(&self.input)
-            .context(format!("reading descriptor {}", self.input.display()))?;
-        descriptor.to_path(open_output_file(&self.output, false)?)?;
-        Ok(())
+        let mut builder = DescriptorBuilder::default();
+        for source in std::iter::once(&self.input).chain(&self.sources) {
+            builder
+                .add_reader(&source.name, open_input_file(&source.path)?)
+                .context(format!("reading csv {}", source.path.display()))?;
+        }
+        for path in &self.allow_override {
+            builder
+                .add_removals_reader(open_input_file(path)?)
+                .context(format!("reading allow override {}", path.display()))?;
+        }
+        if let Some(path) = &self.reasons {
+            let codes = ReasonCodes::from_reader(open_input_file(path)?)
+                .context(format!("reading reason codes {}", path.display()))?;
+            builder.reason_codes(codes);
+        }
+        if self.promote_edges.is_some() || self.promote_fraction.is_some() {
+            let mut rule = PromotionRule {
+                max_edges: self.promote_edges,
+                min_fraction: self.promote_fraction,
+                reason: self.promote_reason.clone(),
+                ..Default::default()
+            };
+            if let Some(path) = &self.observed_peers {
+                rule.read_observed_peers_reader(open_input_file(path)?)
+                    .context(format!("reading observed peers {}", path.display()))?;
+            }
+            builder.promotion(rule);
+        }
+        if let Some(serial) = self.serial {
+            builder.serial(serial);
+        }
+        builder.precedence(&self.precedence);
+        let (descriptor, report) = builder.build()?;
+        descriptor.to_writer(open_output_file(&self.output, false)?, self.compression)?;
+        print_json(&report)
+    }
+}
+
+/// A named csv source, given as `name=path` or as a path named after its file
+/// name
+#[derive(Debug, Clone)]
+struct CsvSource {
+    name: String,
+    path: PathBuf,
+}
+
+impl FromStr for CsvSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((name, path)) = s.split_once('=') {
+            if name.is_empty() || path.is_empty() {
+                anyhow::bail!("invalid source {s}, expected name=path");
+            }
+            return Ok(Self {
+                name: name.to_string(),
+                path: PathBuf::from(path),
+            });
+        }
+        let path = PathBuf::from(s);
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| anyhow::anyhow!("invalid source {s}"))?
+            .to_string();
+        Ok(Self { name, path })
     }
 }
 
@@ -71,7 +187,7 @@ pub struct CountEdges {
 
 impl CountEdges {
     pub fn run(&self) -> Result<()> {
-        let descriptor = Descriptor::from_path(&self.input)
+        let descriptor = Descriptor::from_reader(open_input_file(&self.input)?)
             .context(format!("reading descriptor {}", self.input.display()))?;
         let counts = descriptor.edge_counts();
         let file = open_output_file(&self.output, false)?;
@@ -93,7 +209,7 @@ pub struct Find {
 
 impl Find {
     pub fn run(&self) -> Result<()> {
-        let descriptor = Descriptor::from_path(&self.input)
+        let descriptor = Descriptor::from_reader(open_input_file(&self.input)?)
             .context(format!("reading descriptor {}", self.input.display()))?;
 
         let mut json = json!({});
@@ -119,21 +235,141 @@ pub struct Info {
 
 impl Info {
     pub fn run(&self) -> Result<()> {
-        let descriptor = Descriptor::from_path(&self.input)
+        let descriptor = Descriptor::from_reader(open_input_file(&self.input)?)
             .context(format!("reading descriptor {}", self.input.display()))?;
 
         let node_count = descriptor.nodes.len();
         let (key_count, edge_count) = descriptor
             .edges
+            .as_ref()
             .map(|edges| (edges.keys.len(), edges.edges.len()))
             .unwrap_or((0, 0));
-        let json = json!({
+        let mut json = json!({
             "nodes": node_count,
             "edges": {
                 "edges": edge_count,
                 "keys": key_count,
-            }
+            },
+            "reasons": descriptor.reason_counts(),
         });
+        if let Some(header) = &descriptor.header {
+            json["header"] = json!({
+                "version": format!("{}.{}", header.major_version, header.minor_version),
+                "created_at": header.created_at,
+                "generator_version": header.generator_version,
+                "serial": header.serial,
+            });
+        }
         print_json(&json)
     }
 }
+
+/// Print statistics about the nodes and edges of a descriptor file
+///
+/// This includes the distribution of edges per edge key, the most connected
+/// edge keys, the number of nodes and edges per reason and carryover, the
+/// connected components of the edge graph and the number of edge keys that
+/// are also full nodes.
+#[derive(clap::Args, Debug)]
+pub struct Stats {
+    /// The descriptor file to print statistics for
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    input: PathBuf,
+    /// The number of most connected edge keys to list
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+    /// Print tables instead of json
+    #[arg(long)]
+    table: bool,
+}
+
+impl Stats {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_reader(open_input_file(&self.input)?)
+            .context(format!("reading descriptor {}", self.input.display()))?;
+        let stats = DescriptorStats::new(&descriptor, self.top);
+        if !self.table {
+            return print_json(&stats);
+        }
+
+        println!("nodes                {}", stats.nodes);
+        println!("edges                {}", stats.edges);
+        println!("edge keys            {}", stats.edge_keys);
+        println!("edge keys with node  {}", stats.edge_keys_with_node);
+        println!("components           {}", stats.components);
+        println!("largest component    {}", stats.largest_component);
+
+        println!();
+        println!("{:>10} {:>10}", "edges", "keys");
+        for (degree, keys) in &stats.degrees {
+            println!("{degree:>10} {keys:>10}");
+        }
+
+        println!();
+        println!("{:<52} {:>10}", "most connected", "edges");
+        for entry in &stats.top {
+            println!("{:<52} {:>10}", entry.key.to_string(), entry.edges);
+        }
+
+        println!();
+        println!("{:<20} {:>10} {:>10}", "reason", "nodes", "edges");
+        for (reason, count) in &stats.reasons {
+            println!("{reason:<20} {:>10} {:>10}", count.nodes, count.edges);
+        }
+
+        println!();
+        println!("{:<20} {:>10} {:>10}", "carryover", "nodes", "edges");
+        for (carryover, count) in &stats.carryover {
+            println!("{carryover:<20} {:>10} {:>10}", count.nodes, count.edges);
+        }
+        Ok(())
+    }
+}
+
+/// Export the edges of a descriptor file as a graph
+///
+/// The graph has the edge keys as vertices and the reason, carryover and
+/// provenance of every edge as edge attributes.
+#[derive(clap::Args, Debug)]
+pub struct Graph {
+    /// The descriptor file to export the edges of
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    input: PathBuf,
+    /// The graph format to write
+    #[arg(long, short, value_enum, default_value_t = GraphFormat::Dot)]
+    format: GraphFormat,
+    /// Only export the neighborhood of this key
+    #[arg(long, short)]
+    key: Option<PublicKey>,
+    /// The number of hops from the key to include in the neighborhood
+    #[arg(long, default_value_t = 1, requires = "key")]
+    hops: usize,
+    /// The file to write the graph to
+    #[arg(long, short, default_value = "-")]
+    output: PathBuf,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum GraphFormat {
+    Dot,
+    Graphml,
+    Edgelist,
+}
+
+impl Graph {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_reader(open_input_file(&self.input)?)
+            .context(format!("reading descriptor {}", self.input.display()))?;
+        let mut graph = DescriptorGraph::new(&descriptor);
+        if let Some(key) = &self.key {
+            graph = graph.neighborhood(&key.clone().into(), self.hops)?;
+        }
+        let writer = std::io::BufWriter::new(open_output_file(&self.output, false)?);
+        match self.format {
+            GraphFormat::Dot => graph.write_dot(writer)?,
+            GraphFormat::Graphml => graph.write_graphml(writer)?,
+            GraphFormat::Edgelist => graph.write_edgelist(writer)?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use crate::cmd::OutputFormat;
+use serde_json::{json, Value};
+use std::process::ExitCode;
+
+/// Failures detected by the command line tool itself, as opposed to errors
+/// bubbling up from the library or the filesystem.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("filter hash {actual} does not match manifest hash {expected}")]
+    HashMismatch { expected: String, actual: String },
+    #[error("filter does not verify against {address}")]
+    SignatureInvalid { address: String },
+    #[error("bundle does not verify: {}", failed.join(", "))]
+    BundleInvalid { failed: Vec<String> },
+}
+
+impl Error {
+    pub fn hash_mismatch(expected: &str, actual: &str) -> Self {
+        Self::HashMismatch {
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        }
+    }
+
+    pub fn signature_invalid<T: ToString>(address: T) -> Self {
+        Self::SignatureInvalid {
+            address: address.to_string(),
+        }
+    }
+
+    pub fn bundle_invalid<T: ToString>(failed: &[T]) -> Self {
+        Self::BundleInvalid {
+            failed: failed.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn details(&self) -> Value {
+        match self {
+            Self::HashMismatch { expected, actual } => json!({
+                "expected": expected,
+                "actual": actual,
+            }),
+            Self::SignatureInvalid { address } => json!({
+                "address": address,
+            }),
+            Self::BundleInvalid { failed } => json!({
+                "failed": failed,
+            }),
+        }
+    }
+}
+
+/// Stable classification of a command failure.
+///
+/// The code strings and exit codes are part of the command line interface and
+/// must not change once released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Other,
+    Io,
+    Decode,
+    Crypto,
+    Filter,
+    HashMismatch,
+    SignatureInvalid,
+    BundleInvalid,
+    ChainInvalid,
+    PolicyUnsatisfied,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::Io => "io",
+            Self::Decode => "decode",
+            Self::Crypto => "crypto",
+            Self::Filter => "filter",
+            Self::HashMismatch => "hash_mismatch",
+            Self::SignatureInvalid => "signature_invalid",
+            Self::BundleInvalid => "bundle_invalid",
+            Self::ChainInvalid => "chain_invalid",
+            Self::PolicyUnsatisfied => "policy_unsatisfied",
+        }
+    }
+
+    /// The process exit code for this class of failure. Exit code 2 is
+    /// reserved for command line usage errors reported by clap.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::Io => 3,
+            Self::Decode => 4,
+            Self::Crypto => 5,
+            Self::Filter => 6,
+            Self::HashMismatch => 10,
+            Self::SignatureInvalid => 11,
+            Self::BundleInvalid => 12,
+            Self::ChainInvalid => 13,
+            Self::PolicyUnsatisfied => 14,
+        }
+    }
+
+    /// Classifies an error by the outermost cause in its chain that has a
+    /// known class.
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(Self::from_cause)
+            .unwrap_or(Self::Other)
+    }
+
+    fn from_cause(cause: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            return Some(match err {
+                Error::HashMismatch { .. } => Self::HashMismatch,
+                Error::SignatureInvalid { .. } => Self::SignatureInvalid,
+                Error::BundleInvalid { .. } => Self::BundleInvalid,
+            });
+        }
+        if let Some(err) = cause.downcast_ref::<xorf_generator::Error>() {
+            use xorf_generator::{verify::Error as VerifyError, Error as LibError};
+            return Some(match err {
+                LibError::Io(_) | LibError::Grpc(_) => Self::Io,
+                LibError::Bincode(_)
+                | LibError::Json(_)
+                | LibError::Csv(_)
+                | LibError::Proto(_)
+                | LibError::Base64(_)
+                | LibError::Bundle(_)
+                | LibError::Descriptor(_) => Self::Decode,
+                LibError::Crypto(_) | LibError::Pkcs11(_) => Self::Crypto,
+                LibError::Filter(_) => Self::Filter,
+                LibError::Verify(err) => match err {
+                    VerifyError::InvalidSignature | VerifyError::UnsupportedKey(_) => Self::Crypto,
+                    VerifyError::Malformed(_) | VerifyError::UnsupportedVersion(_) => Self::Filter,
+                },
+                LibError::Rotation(_) => Self::ChainInvalid,
+                LibError::Policy(_) => Self::PolicyUnsatisfied,
+            });
+        }
+        if cause.is::<std::io::Error>() {
+            return Some(Self::Io);
+        }
+        if cause.is::<serde_json::Error>() || cause.is::<base64::DecodeError>() {
+            return Some(Self::Decode);
+        }
+        if cause.is::<helium_crypto::Error>() {
+            return Some(Self::Crypto);
+        }
+        None
+    }
+}
+
+/// Reports the given error in the requested output format and returns the
+/// exit code for its failure class.
+pub fn report(err: &anyhow::Error, format: OutputFormat) -> ExitCode {
+    let code = ErrorCode::from_error(err);
+    match format {
+        OutputFormat::Text => eprintln!("Error: {err:?}"),
+        OutputFormat::Json => {
+            let mut json = json!({
+                "error": {
+                    "code": code.as_str(),
+                    "message": err.to_string(),
+                    "context": err.chain().skip(1).map(|cause| cause.to_string()).collect::<Vec<_>>(),
+                }
+            });
+            if let Some(cmd_err) = err.chain().find_map(|cause| cause.downcast_ref::<Error>()) {
+                json["error"]["details"] = cmd_err.details();
+            }
+            match serde_json::to_string_pretty(&json) {
+                Ok(json) => eprintln!("{json}"),
+                Err(_) => eprintln!("Error: {err:?}"),
+            }
+        }
+    }
+    ExitCode::from(code.exit_code())
+}
@@ -1,9 +1,13 @@
-use crate::cmd::{open_output_file, print_json};
+use crate::cmd::{
+    error::Error, open_input_file, open_output_file, print_json, read_input_file, read_public_key,
+};
 use anyhow::{Context, Result};
 use helium_crypto::PublicKey;
-use serde_json::json;
+use serde_json::{json, Value};
 use std::{io::Write, path::PathBuf};
-use xorf_generator::{base64_serde, Filter, Manifest, PublicKeyManifest, FILTTER_VERSION};
+use xorf_generator::{
+    base64_serde, verify_chain, Filter, KeyRotation, Manifest, PolicyVerify, PublicKeyManifest,
+};
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
@@ -51,7 +55,7 @@ pub struct Contains {
 
 impl Contains {
     pub fn run(&self) -> Result<()> {
-        let filter = Filter::from_path(&self.input)
+        let filter = Filter::from_bytes(&read_input_file(&self.input)?)
             .context(format!("reading filter {}", self.input.display()))?;
         let source = self.key.clone().into();
         let in_filter = if let Some(target) = self.target.clone() {
@@ -76,20 +80,34 @@ pub struct Verify {
     /// The public key to use for verification
     #[arg(long, short, default_value = "public_key.json")]
     key: PathBuf,
+    /// Key rotation files, in chain order, to follow from the given public
+    /// key to the key the filter was signed with
+    #[arg(long)]
+    chain: Vec<PathBuf>,
 }
 
 impl Verify {
     pub fn run(&self) -> Result<()> {
-        let filter = Filter::from_path(&self.input)
+        let filter = Filter::from_bytes(&read_input_file(&self.input)?)
             .context(format!("reading filter {}", self.input.display()))?;
-        let key_manifest = PublicKeyManifest::from_path(&self.key)
-            .context(format!("reading public key {}", self.key.display()))?;
-        let key = key_manifest.public_key()?;
-        let verified = filter.verify(&key).is_ok();
-        if !verified {
-            anyhow::bail!("Filter does not verify");
-        }
-        print_verified(&key, verified)
+        let key_manifest = read_public_key(&self.key)?;
+        let root = key_manifest.public_key()?;
+        let chain = self
+            .chain
+            .iter()
+            .map(|path| {
+                KeyRotation::from_reader(open_input_file(path)?)
+                    .context(format!("reading rotation {}", path.display()))
+            })
+            .collect::<Result<Vec<KeyRotation>>>()?;
+        verify_chain(&root, &chain)?;
+        // the key manifest at the end of the chain carries the signing policy
+        let key_manifest = chain
+            .last()
+            .map(|rotation| rotation.to.clone())
+            .unwrap_or(key_manifest);
+        let policy = verify_filter(&filter, &key_manifest)?;
+        print_verified(&key_manifest, policy)
     }
 }
 
@@ -119,51 +137,97 @@ pub struct Generate {
 
 impl Generate {
     pub fn run(&self) -> Result<()> {
-        let manifest = Manifest::from_path(&self.manifest)
+        let manifest = Manifest::from_reader(open_input_file(&self.manifest)?)
             .context(format!("reading manifest {}", self.manifest.display()))?;
-        let key_manifest = PublicKeyManifest::from_path(&self.key)
-            .context(format!("reading public key {}", self.key.display()))?;
-        let key = key_manifest.public_key()?;
+        let key_manifest = read_public_key(&self.key)?;
 
-        let mut filter = Filter::from_signing_path(&self.data, FILTTER_VERSION)?;
+        let mut filter =
+            Filter::from_signing_bytes(&read_input_file(&self.data)?, manifest.version)
+                .context(format!("reading filter {}", self.data.display()))?;
         filter.signature = manifest.sign(&key_manifest)?;
         filter.serial = manifest.serial;
         let filter_bytes = filter.to_bytes()?;
         let mut file = open_output_file(&self.output, false)?;
         file.write_all(&filter_bytes)?;
 
-        let verified = filter.verify(&key).is_ok();
-        if !verified {
-            anyhow::bail!("Filter does not verify");
-        }
-        print_verified(&key, verified)
+        let policy = verify_filter(&filter, &key_manifest)?;
+        print_verified(&key_manifest, policy)
     }
 }
 
 /// Displays filter information for a given filter
+///
+/// This includes the theoretical false positive rate for the filter kind and
+/// fingerprint width, as well as an empirical rate measured by checking random
+/// public keys against the filter.
 #[derive(clap::Args, Debug)]
 pub struct Info {
     /// The input file to generate a filter for
     #[arg(long, short, default_value = "filter.bin")]
     input: PathBuf,
+    /// The number of random public keys to check for the empirical false
+    /// positive rate. Use 0 to skip the empirical test
+    #[arg(long, default_value_t = 1_000_000)]
+    fpr_samples: usize,
 }
 
 impl Info {
     pub fn run(&self) -> Result<()> {
-        let filter = Filter::from_path(&self.input)
+        let filter = Filter::from_bytes(&read_input_file(&self.input)?)
             .context(format!("reading filter {}", self.input.display()))?;
 
-        let mut json = serde_json::to_value(&filter)?;
-        json["fingerprints"] = filter.len().into();
-        json["hash"] = base64_serde::encode(&filter.hash()?).into();
+        let mut json = filter_info(&filter)?;
+        if self.fpr_samples > 0 {
+            let false_positives = filter.sample_false_positives(self.fpr_samples);
+            let empirical = json!({
+                "samples": self.fpr_samples,
+                "false_positives": false_positives,
+                "rate": false_positives as f64 / self.fpr_samples as f64,
+            });
+            json["false_positive_rate"]["empirical"] = empirical;
+        }
         print_json(&json)
     }
 }
 
-fn print_verified(public_key: &PublicKey, verified: bool) -> Result<()> {
-    let json = json!({
-        "address":  public_key.to_string(),
-        "verified": verified,
+/// Describes the given filter: its header, kind, size and theoretical false
+/// positive rate.
+pub fn filter_info(filter: &Filter) -> Result<Value> {
+    let mut json = serde_json::to_value(filter)?;
+    json["kind"] = filter.filter.kind().into();
+    json["fingerprints"] = filter.len().into();
+    json["fingerprint_bits"] = filter.filter.fingerprint_bits().into();
+    json["estimated_entries"] = filter.filter.estimated_entries().into();
+    json["bits_per_entry"] = filter.filter.bits_per_entry().into();
+    json["size"] = filter.to_bytes()?.len().into();
+    json["hash"] = base64_serde::encode(&filter.hash()?).into();
+    json["false_positive_rate"] = json!({
+        "theoretical": filter.filter.false_positive_rate(),
+    });
+    Ok(json)
+}
+
+/// Verifies the filter against the given key manifest, reporting a failed
+/// signature check and an unsatisfied signing policy as distinct errors.
+fn verify_filter(filter: &Filter, key_manifest: &PublicKeyManifest) -> Result<PolicyVerify> {
+    filter
+        .verify_with_policy(key_manifest)
+        .map_err(|err| match err {
+            xorf_generator::Error::Policy(_) => anyhow::Error::from(err),
+            _ => match key_manifest.public_key() {
+                Ok(key) => Error::signature_invalid(key).into(),
+                Err(err) => err.into(),
+            },
+        })
+}
+
+fn print_verified(key_manifest: &PublicKeyManifest, policy: PolicyVerify) -> Result<()> {
+    let mut json = json!({
+        "address":  key_manifest.public_key()?.to_string(),
+        "verified": policy.satisfied,
     });
+    if !key_manifest.policy.is_empty() {
+        json["policy"] = serde_json::to_value(policy)?;
+    }
     print_json(&json)
 }
@@ -1,8 +1,8 @@
-use crate::cmd::print_json;
+use crate::cmd::{open_input_file, open_output_file, print_json, read_public_key};
 use anyhow::{Context, Result};
 use serde_json::json;
-use std::path::PathBuf;
-use xorf_generator::PublicKeyManifest;
+use std::{io::Write, path::PathBuf};
+use xorf_generator::{verify_chain, KeyRotation, PublicKeyManifest};
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
@@ -20,12 +20,16 @@ impl Cmd {
 #[derive(clap::Subcommand, Debug)]
 pub enum KeyCommand {
     Info(Info),
+    Rotate(Rotate),
+    VerifyChain(VerifyChain),
 }
 
 impl KeyCommand {
     pub fn run(&self) -> Result<()> {
         match self {
             Self::Info(cmd) => cmd.run(),
+            Self::Rotate(cmd) => cmd.run(),
+            Self::VerifyChain(cmd) => cmd.run(),
         }
     }
 }
@@ -40,17 +44,104 @@ pub struct Info {
 
 impl Info {
     pub fn run(&self) -> Result<()> {
-        let manifest = PublicKeyManifest::from_path(&self.input)
-            .context(format!("reading public key {}", self.input.display()))?;
+        let manifest = read_public_key(&self.input)?;
         print_manifest(&manifest)
     }
 }
 
+/// Generate a key rotation statement from the current to a new multisig key
+///
+/// This generates a rotation file with a signature entry for each member of
+/// the current key, as well as a binary file with the data the members of the
+/// current key sign to authorize the new key.
+#[derive(Debug, clap::Args)]
+pub struct Rotate {
+    /// The current public key file
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+    /// The new public key file
+    #[arg(long, short)]
+    new: PathBuf,
+    /// The file to write the resulting rotation statement to
+    #[arg(long, short, default_value = "rotation.json")]
+    output: PathBuf,
+    /// The file to write the signing data for the rotation to
+    #[arg(long, short, default_value = "rotation.bin")]
+    data: PathBuf,
+    /// Whether to force overwrite an existing rotation file
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl Rotate {
+    pub fn run(&self) -> Result<()> {
+        let from = read_public_key(&self.key)?;
+        let to = read_public_key(&self.new)?;
+        let rotation = KeyRotation::new(from, to);
+
+        let mut rotation_file = open_output_file(&self.output, !self.force)?;
+        let mut data_file = open_output_file(&self.data, false)?;
+        serde_json::to_writer_pretty(&mut rotation_file, &rotation)?;
+        data_file.write_all(&rotation.to_signing_bytes()?)?;
+
+        let json = json!({
+            "from": rotation.from.public_key()?.to_string(),
+            "to": rotation.to.public_key()?.to_string(),
+            "required": rotation.from.required,
+        });
+        print_json(&json)
+    }
+}
+
+/// Verify a chain of key rotations starting at a pinned root key
+///
+/// Each rotation in the chain must be signed by the required number of
+/// members of the key the previous rotation (or the root key) ends at.
+#[derive(Debug, clap::Args)]
+pub struct VerifyChain {
+    /// The pinned root public key file
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+    /// The rotation files in chain order
+    #[arg(required = true)]
+    rotations: Vec<PathBuf>,
+}
+
+impl VerifyChain {
+    pub fn run(&self) -> Result<()> {
+        let root = read_public_key(&self.key)?.public_key()?;
+        let chain = self
+            .rotations
+            .iter()
+            .map(|path| {
+                KeyRotation::from_reader(open_input_file(path)?)
+                    .context(format!("reading rotation {}", path.display()))
+            })
+            .collect::<Result<Vec<KeyRotation>>>()?;
+        let mut current = root.clone();
+        for (rotation, path) in chain.iter().zip(&self.rotations) {
+            current = verify_chain(&current, std::slice::from_ref(rotation))
+                .context(format!("verifying rotation {}", path.display()))?;
+        }
+
+        let json = json!({
+            "root": root.to_string(),
+            "rotations": chain.len(),
+            "address": current.to_string(),
+            "verified": true,
+        });
+        print_json(&json)
+    }
+}
+
 fn print_manifest(manifest: &PublicKeyManifest) -> Result<()> {
-    let json = json!({
+    let mut json = json!({
         "address": manifest.public_key()?.to_string(),
         "keys": manifest.public_keys().len(),
         "required": manifest.required,
     });
+    if !manifest.policy.is_empty() {
+        json["policy"] = serde_json::to_value(&manifest.policy)?;
+    }
     print_json(&json)
 }
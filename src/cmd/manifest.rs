@@ -1,10 +1,21 @@
-use crate::cmd::{open_output_file, print_json};
+use crate::cmd::{
+    error::Error, is_stdio, open_input_file, open_output_file, print_json, read_input_file,
+    read_public_key,
+};
 use anyhow::{Context, Result};
+use helium_crypto::PublicKey;
+use serde::Serialize;
 use serde_json::json;
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 use xorf_generator::{
-    base64_serde, Filter, Manifest, ManifestSignature, ManifestSignatureVerify, PublicKeyManifest,
-    FILTTER_VERSION,
+    base64_serde, Attestation, CollectStatus, DetachedSignature, Filter, Manifest,
+    PublicKeyManifest, FILTER_ENVELOPE_VERSION, FILTTER_VERSION,
 };
 
 #[derive(clap::Args, Debug)]
@@ -24,6 +35,10 @@ impl Cmd {
 pub enum ManifestCommand {
     Generate(Generate),
     Verify(Verify),
+    Collect(Collect),
+    Attest(Attest),
+    Envelope(Envelope),
+    Sign(Sign),
 }
 
 impl ManifestCommand {
@@ -31,6 +46,10 @@ impl ManifestCommand {
         match self {
             Self::Generate(cmd) => cmd.run(),
             Self::Verify(cmd) => cmd.run(),
+            Self::Collect(cmd) => cmd.run(),
+            Self::Attest(cmd) => cmd.run(),
+            Self::Envelope(cmd) => cmd.run(),
+            Self::Sign(cmd) => cmd.run(),
         }
     }
 }
@@ -58,28 +77,27 @@ pub struct Generate {
     /// Whether to force overwrite an existing manifest file
     #[arg(long, short)]
     force: bool,
+
+    /// Have members sign the small signing envelope instead of the full
+    /// signing data
+    #[arg(long)]
+    envelope: bool,
 }
 
 impl Generate {
     pub fn run(&self) -> Result<()> {
-        let filter = Filter::from_signing_path(&self.data, FILTTER_VERSION)
+        let version = if self.envelope {
+            FILTER_ENVELOPE_VERSION
+        } else {
+            FILTTER_VERSION
+        };
+        let filter = Filter::from_signing_bytes(&read_input_file(&self.data)?, version)
             .context(format!("reading filter {}", self.data.display()))?;
 
-        let filter_hash = filter.hash()?;
-        let key_manifest = PublicKeyManifest::from_path(&self.key)
-            .context(format!("reading public key {}", self.key.display()))?;
-        let signatures = key_manifest
-            .public_keys
-            .iter()
-            .map(ManifestSignature::from)
-            .collect();
+        let key_manifest = read_public_key(&self.key)?;
 
         let mut manifest_file = open_output_file(&self.manifest, !self.force)?;
-        let manifest = Manifest {
-            serial: filter.serial,
-            hash: base64_serde::encode(&filter_hash),
-            signatures,
-        };
+        let manifest = Manifest::for_filter(&filter, &key_manifest)?;
         serde_json::to_writer_pretty(&mut manifest_file, &manifest)?;
 
         Ok(())
@@ -110,31 +128,25 @@ pub struct Verify {
 
 impl Verify {
     pub fn run(&self) -> Result<()> {
-        let manifest = Manifest::from_path(&self.manifest)
+        let manifest = Manifest::from_reader(open_input_file(&self.manifest)?)
             .context(format!("reading manifest {}", self.manifest.display()))?;
         let manifest_hash = base64_serde::decode(&manifest.hash)?;
-        let key_manifest = PublicKeyManifest::from_path(&self.key)
-            .context(format!("reading public key {}", self.key.display()))?;
+        let key_manifest = read_public_key(&self.key)?;
         let key = key_manifest.public_key()?;
 
-        let filter = Filter::from_signing_path(&self.data, FILTTER_VERSION)
+        let filter = Filter::from_signing_bytes(&read_input_file(&self.data)?, manifest.version)
             .context(format!("reading filter {}", self.data.display()))?;
         let filter_hash = filter.hash()?;
-        let signing_bytes = filter.to_signing_bytes()?;
+        let signing_bytes = filter.signed_message()?;
 
         let hash_verified = manifest_hash == filter_hash;
         if !hash_verified {
-            anyhow::bail!(format!(
-                "Filter hash {} does not match manifest hash {}",
-                base64_serde::encode(&filter_hash),
-                manifest.hash
-            ));
-        }
-        let signtatures: Vec<ManifestSignatureVerify> = manifest
-            .signatures
-            .iter()
-            .map(|signature| signature.verify(&signing_bytes))
-            .collect();
+            return Err(
+                Error::hash_mismatch(&manifest.hash, &base64_serde::encode(&filter_hash)).into(),
+            );
+        }
+        let signtatures = manifest.verify_signatures(&signing_bytes);
+        let policy = key_manifest.evaluate_policy(&manifest.signers(&key_manifest, &signing_bytes));
 
         let json = json!({
             "signing_data": self.data,
@@ -145,7 +157,417 @@ impl Verify {
             },
             "public_key": key,
             "signatures": signtatures,
+            "policy": policy,
+        });
+        print_json(&json)?;
+        if let Some(invalid) = signtatures
+            .iter()
+            .find(|verify| verify.signature().is_signed() && !verify.is_verified())
+        {
+            return Err(Error::signature_invalid(invalid.signature().address()).into());
+        }
+        if !policy.satisfied {
+            return Err(xorf_generator::Error::policy(
+                "Manifest signatures do not satisfy the signing policy",
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Collect detached signature files into the manifest
+///
+/// This reads every `sig-<address>.json` file in the given directory, checks
+/// each against the manifest serial and hash and the signing data, and merges
+/// the valid ones into the manifest. Invalid, duplicate and stale signatures
+/// are reported and left out of the manifest.
+#[derive(Debug, clap::Args)]
+pub struct Collect {
+    /// The directory with the detached signature files
+    dir: PathBuf,
+
+    /// The file with the data bytes that were signed
+    #[arg(long, short, default_value = "data.bin")]
+    data: PathBuf,
+
+    /// The public key file to use
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+
+    /// The manifest file to merge the signatures into
+    #[arg(long, short, default_value = "manifest.json")]
+    manifest: PathBuf,
+}
+
+impl Collect {
+    pub fn run(&self) -> Result<()> {
+        let mut manifest = Manifest::from_reader(open_input_file(&self.manifest)?)
+            .context(format!("reading manifest {}", self.manifest.display()))?;
+        let key_manifest = read_public_key(&self.key)?;
+        let filter = Filter::from_signing_bytes(&read_input_file(&self.data)?, manifest.version)
+            .context(format!("reading filter {}", self.data.display()))?;
+        let filter_hash = base64_serde::encode(&filter.hash()?);
+        if manifest.hash != filter_hash {
+            return Err(Error::hash_mismatch(&manifest.hash, &filter_hash).into());
+        }
+        let signing_bytes = filter.signed_message()?;
+
+        let mut paths = fs::read_dir(&self.dir)
+            .context(format!("reading directory {}", self.dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        paths.retain(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with("sig-") && name.ends_with(".json"))
+                .unwrap_or(false)
+        });
+        paths.sort();
+
+        let collected: Vec<Collected> = paths
+            .into_iter()
+            .map(|path| match DetachedSignature::from_path(&path) {
+                Ok(detached) => Collected {
+                    address: Some(detached.address.to_string()),
+                    status: manifest.collect(&detached, &key_manifest, &signing_bytes),
+                    file: path,
+                },
+                Err(err) => Collected {
+                    address: None,
+                    status: CollectStatus::Invalid(err.to_string()),
+                    file: path,
+                },
+            })
+            .collect();
+        let merged = collected
+            .iter()
+            .filter(|collected| collected.status == CollectStatus::Merged)
+            .count();
+
+        // A manifest read from stdin is always written to stdout
+        if merged > 0 || is_stdio(&self.manifest) {
+            let mut manifest_file = open_output_file(&self.manifest, false)?;
+            serde_json::to_writer_pretty(&mut manifest_file, &manifest)?;
+        }
+
+        let json = json!({
+            "manifest": self.manifest,
+            "serial": manifest.serial,
+            "merged": merged,
+            "rejected": collected.len() - merged,
+            "signatures": collected,
+        });
+        print_json(&json)
+    }
+}
+
+/// Prepare an attestation of what was reviewed before signing
+///
+/// This records the hash of the reviewed descriptor, and optionally the hash of
+/// a reviewed diff and a comment, for the signing data of the given manifest,
+/// or its signing envelope for a manifest generated with `--envelope`. It
+/// prints the attestation and writes the bytes to sign for it. The signature
+/// over those bytes goes in the attestation `signature` field, and the
+/// attestation is then added to the member's manifest or detached signature
+/// entry.
+#[derive(Debug, clap::Args)]
+pub struct Attest {
+    /// The signing data the attestation is for
+    #[arg(long, short, default_value = "data.bin")]
+    data: PathBuf,
+
+    /// The manifest the attestation is for
+    #[arg(long, short, default_value = "manifest.json")]
+    manifest: PathBuf,
+
+    /// The descriptor that was reviewed
+    #[arg(long, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+
+    /// The diff against the previous descriptor that was reviewed
+    #[arg(long)]
+    diff: Option<PathBuf>,
+
+    /// An optional comment by the signer
+    #[arg(long)]
+    comment: Option<String>,
+
+    /// The unix time in seconds of the attestation. Defaults to now
+    #[arg(long)]
+    timestamp: Option<u64>,
+
+    /// The file to write the attestation bytes to sign to
+    #[arg(long, short, default_value = "attestation.bin")]
+    output: PathBuf,
+
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl Attest {
+    pub fn run(&self) -> Result<()> {
+        let manifest = Manifest::from_reader(open_input_file(&self.manifest)?)
+            .context(format!("reading manifest {}", self.manifest.display()))?;
+        let filter = Filter::from_signing_bytes(&read_input_file(&self.data)?, manifest.version)
+            .context(format!("reading filter {}", self.data.display()))?;
+        let filter_hash = base64_serde::encode(&filter.hash()?);
+        if manifest.hash != filter_hash {
+            return Err(Error::hash_mismatch(&manifest.hash, &filter_hash).into());
+        }
+        let timestamp = match self.timestamp {
+            Some(timestamp) => timestamp,
+            None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+        let attestation = Attestation {
+            timestamp,
+            descriptor_hash: file_hash(&self.descriptor)?,
+            diff_hash: self.diff.as_deref().map(file_hash).transpose()?,
+            comment: self.comment.clone(),
+            signature: vec![],
+        };
+        let signing_bytes = attestation.to_signing_bytes(&filter.signed_message_hash()?)?;
+        let mut file = open_output_file(&self.output, !self.force)?;
+        file.write_all(&signing_bytes)?;
+
+        let json = json!({
+            "signing_data": self.output,
+            "serial": filter.serial,
+            "attestation": attestation,
         });
         print_json(&json)
     }
 }
+
+/// Write the signing envelope for a manifest
+///
+/// For a manifest generated with `--envelope`, members sign a small envelope
+/// with the filter version, serial, and hashes of the filter data and metadata
+/// instead of the full signing data. This checks the signing data against the
+/// manifest hash and writes the envelope bytes to sign.
+#[derive(Debug, clap::Args)]
+pub struct Envelope {
+    /// The signing data to write the envelope for
+    #[arg(long, short, default_value = "data.bin")]
+    data: PathBuf,
+
+    /// The manifest the envelope is for
+    #[arg(long, short, default_value = "manifest.json")]
+    manifest: PathBuf,
+
+    /// The file to write the envelope bytes to sign to
+    #[arg(long, short, default_value = "envelope.bin")]
+    output: PathBuf,
+
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl Envelope {
+    pub fn run(&self) -> Result<()> {
+        let manifest = Manifest::from_reader(open_input_file(&self.manifest)?)
+            .context(format!("reading manifest {}", self.manifest.display()))?;
+        if manifest.version != FILTER_ENVELOPE_VERSION {
+            anyhow::bail!(
+                "manifest version {} does not use signing envelopes",
+                manifest.version
+            );
+        }
+        let filter = Filter::from_signing_bytes(&read_input_file(&self.data)?, manifest.version)
+            .context(format!("reading filter {}", self.data.display()))?;
+        let filter_hash = base64_serde::encode(&filter.hash()?);
+        if manifest.hash != filter_hash {
+            return Err(Error::hash_mismatch(&manifest.hash, &filter_hash).into());
+        }
+        let envelope = filter.to_envelope_bytes()?;
+        let mut file = open_output_file(&self.output, !self.force)?;
+        file.write_all(&envelope)?;
+
+        let json = json!({
+            "signing_data": self.output,
+            "version": manifest.version,
+            "serial": manifest.serial,
+            "size": envelope.len(),
+        });
+        print_json(&json)
+    }
+}
+
+/// Sign the signing data of a manifest
+///
+/// This signs the signing data, or the signing envelope for a manifest
+/// generated with `--envelope`, with a local keypair file or a key held in a
+/// PKCS#11 token, and adds the signature to the manifest or writes it as a
+/// detached signature file.
+#[derive(Debug, clap::Args)]
+#[command(group(clap::ArgGroup::new("signer").required(true)))]
+pub struct Sign {
+    /// The file with the data bytes to sign
+    #[arg(long, short, default_value = "data.bin")]
+    data: PathBuf,
+
+    /// The public key file to use
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+
+    /// The manifest to sign
+    #[arg(long, short, default_value = "manifest.json")]
+    manifest: PathBuf,
+
+    /// The keypair file to sign with
+    #[arg(long, group = "signer")]
+    keypair: Option<PathBuf>,
+
+    #[cfg(feature = "pkcs11")]
+    #[command(flatten)]
+    pkcs11: Pkcs11Args,
+
+    /// Write a detached signature file to the given directory instead of
+    /// adding the signature to the manifest
+    #[arg(long)]
+    detached: Option<PathBuf>,
+
+    /// Whether to force overwrite an existing detached signature file
+    #[arg(long, short)]
+    force: bool,
+}
+
+/// Options for signing with a key in a PKCS#11 token. The token user PIN is
+/// read from the XORF_PKCS11_PIN environment variable. The token key is on the
+/// network of the keys in the public key file.
+#[cfg(feature = "pkcs11")]
+#[derive(Debug, clap::Args)]
+pub struct Pkcs11Args {
+    /// The PKCS#11 module to sign with
+    #[arg(long, group = "signer", requires_all = ["pkcs11_token", "pkcs11_key"])]
+    pkcs11_module: Option<PathBuf>,
+
+    /// The label of the token with the signing key
+    #[arg(long)]
+    pkcs11_token: Option<String>,
+
+    /// The label of the signing key in the token
+    #[arg(long)]
+    pkcs11_key: Option<String>,
+}
+
+enum Signer {
+    Keypair(helium_crypto::Keypair),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(xorf_generator::Pkcs11Signer),
+}
+
+impl Signer {
+    fn public_key(&self) -> &PublicKey {
+        match self {
+            Self::Keypair(keypair) => keypair.public_key(),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(signer) => signer.public_key(),
+        }
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Keypair(keypair) => Ok(helium_crypto::Sign::sign(keypair, msg)?),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(signer) => Ok(signer.sign(msg)?),
+        }
+    }
+}
+
+impl Sign {
+    #[cfg_attr(not(feature = "pkcs11"), allow(unused_variables))]
+    fn signer(&self, key_manifest: &PublicKeyManifest) -> Result<Signer> {
+        #[cfg(feature = "pkcs11")]
+        if let Some(module) = &self.pkcs11.pkcs11_module {
+            let network = key_manifest
+                .public_keys
+                .first()
+                .map(|key| key.network)
+                .ok_or_else(|| anyhow::anyhow!("{} has no public keys", self.key.display()))?;
+            let pin = std::env::var("XORF_PKCS11_PIN")
+                .context("reading token pin from XORF_PKCS11_PIN")?;
+            let token = self.pkcs11.pkcs11_token.as_deref().unwrap_or_default();
+            let label = self.pkcs11.pkcs11_key.as_deref().unwrap_or_default();
+            let signer = xorf_generator::Pkcs11Signer::open(module, token, &pin, label, network)
+                .context(format!("opening pkcs11 module {}", module.display()))?;
+            return Ok(Signer::Pkcs11(signer));
+        }
+        let path = self
+            .keypair
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("no keypair to sign with"))?;
+        let data = read_input_file(path)?;
+        let keypair = helium_crypto::Keypair::try_from(&data[..])
+            .context(format!("reading keypair {}", path.display()))?;
+        Ok(Signer::Keypair(keypair))
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let mut manifest = Manifest::from_reader(open_input_file(&self.manifest)?)
+            .context(format!("reading manifest {}", self.manifest.display()))?;
+        let key_manifest = read_public_key(&self.key)?;
+        let filter = Filter::from_signing_bytes(&read_input_file(&self.data)?, manifest.version)
+            .context(format!("reading filter {}", self.data.display()))?;
+        let filter_hash = base64_serde::encode(&filter.hash()?);
+        if manifest.hash != filter_hash {
+            return Err(Error::hash_mismatch(&manifest.hash, &filter_hash).into());
+        }
+        let signing_bytes = filter.signed_message()?;
+
+        let signer = self.signer(&key_manifest)?;
+        let address = signer.public_key().clone();
+        if !key_manifest.public_keys().contains(&address) {
+            anyhow::bail!("{address} is not a public key in {}", self.key.display());
+        }
+        let detached = DetachedSignature {
+            address: address.clone().into(),
+            hash: manifest.hash.clone(),
+            serial: manifest.serial,
+            signature: signer.sign(&signing_bytes)?,
+            attestation: None,
+        };
+
+        if let Some(dir) = &self.detached {
+            let path = dir.join(DetachedSignature::file_name(&address));
+            let mut file = open_output_file(&path, !self.force)?;
+            serde_json::to_writer_pretty(&mut file, &detached)?;
+            return print_json(&json!({
+                "address": address.to_string(),
+                "file": path,
+            }));
+        }
+
+        let status = manifest.collect(&detached, &key_manifest, &signing_bytes);
+        if let CollectStatus::Invalid(reason) = &status {
+            anyhow::bail!("signature by {address} is invalid: {reason}");
+        }
+        if status == CollectStatus::Merged || is_stdio(&self.manifest) {
+            let mut manifest_file = open_output_file(&self.manifest, false)?;
+            serde_json::to_writer_pretty(&mut manifest_file, &manifest)?;
+        }
+        print_json(&Collected {
+            file: self.manifest.clone(),
+            address: Some(address.to_string()),
+            status,
+        })
+    }
+}
+
+/// Returns the base64 encoded sha256 hash of the given file
+fn file_hash(path: &Path) -> Result<String> {
+    Ok(base64_serde::encode(&Sha256::digest(read_input_file(
+        path,
+    )?)))
+}
+
+#[derive(Debug, Serialize)]
+struct Collected {
+    file: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    #[serde(flatten)]
+    status: CollectStatus,
+}
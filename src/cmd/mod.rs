@@ -1,23 +1,122 @@
-use std::{fs, path::Path};
+use anyhow::Context;
+use helium_crypto::Network;
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+use xorf_generator::PublicKeyManifest;
 
+pub mod bundle;
+pub mod config;
 pub mod data;
 pub mod descriptor;
+pub mod error;
 pub mod filter;
 pub mod key;
 pub mod manifest;
+pub mod pipeline;
+pub mod serve;
 
+/// The format used to report command failures
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Whether an input was read from stdin or an output written to stdout. Each
+/// can only be used by one argument of a command.
+static STDIN_USED: AtomicBool = AtomicBool::new(false);
+static STDOUT_USED: AtomicBool = AtomicBool::new(false);
+
+/// The network the keys in public key files have to be on
+static NETWORK: OnceLock<Network> = OnceLock::new();
+
+/// Requires the keys of every public key file that is read to be on the given
+/// network
+pub fn require_network(network: Network) {
+    let _ = NETWORK.set(network);
+}
+
+/// Reads a public key file, checking that its keys are on the required
+/// network if there is one
+pub fn read_public_key(filename: &Path) -> anyhow::Result<PublicKeyManifest> {
+    let manifest = PublicKeyManifest::from_reader(open_input_file(filename)?)
+        .context(format!("reading public key {}", filename.display()))?;
+    if let Some(network) = NETWORK.get() {
+        if let Some(key) = manifest
+            .public_keys()
+            .into_iter()
+            .find(|key| key.network != *network)
+        {
+            anyhow::bail!(
+                "public key {key} in {} is not a {network} key",
+                filename.display()
+            );
+        }
+    }
+    Ok(manifest)
+}
+
+/// Returns whether the given path stands for stdin or stdout
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Prints the given value as json. When an output is written to stdout the
+/// json goes to stderr instead, so it does not end up in the output.
 pub fn print_json<T: ?Sized + serde::Serialize>(value: &T) -> anyhow::Result<()> {
-    println!("{}", serde_json::to_string_pretty(value)?);
+    let json = serde_json::to_string_pretty(value)?;
+    if STDOUT_USED.load(Ordering::Relaxed) {
+        eprintln!("{json}");
+    } else {
+        println!("{json}");
+    }
     Ok(())
 }
 
-pub fn open_output_file(filename: &Path, create_new: bool) -> anyhow::Result<fs::File> {
-    use anyhow::Context;
-    fs::OpenOptions::new()
+/// Opens the given input file, or stdin for `-`
+pub fn open_input_file(filename: &Path) -> anyhow::Result<Box<dyn Read>> {
+    if is_stdio(filename) {
+        if STDIN_USED.swap(true, Ordering::Relaxed) {
+            anyhow::bail!("only one input can be read from stdin");
+        }
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    let file =
+        fs::File::open(filename).context(format!("opening input file {}", filename.display()))?;
+    Ok(Box::new(file))
+}
+
+/// Reads all of the given input file, or stdin for `-`
+pub fn read_input_file(filename: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open_input_file(filename)?
+        .read_to_end(&mut data)
+        .context(format!("reading input file {}", filename.display()))?;
+    Ok(data)
+}
+
+/// Opens the given output file, or stdout for `-`
+pub fn open_output_file(filename: &Path, create_new: bool) -> anyhow::Result<Box<dyn Write>> {
+    if is_stdio(filename) {
+        if STDOUT_USED.swap(true, Ordering::Relaxed) {
+            anyhow::bail!("only one output can be written to stdout");
+        }
+        return Ok(Box::new(io::stdout().lock()));
+    }
+    let file = fs::OpenOptions::new()
         .write(true)
         .create(true)
         .create_new(create_new)
         .truncate(true)
         .open(filename)
-        .context(format!("opening output file {}", filename.display()))
+        .context(format!("opening output file {}", filename.display()))?;
+    Ok(Box::new(file))
 }
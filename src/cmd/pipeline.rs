@@ -0,0 +1,343 @@
+use crate::cmd::{error::Error, print_json, read_public_key};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use xorf_generator::{
+    base64_serde, Descriptor, DescriptorBuilder, Filter, Manifest, PolicyVerify, PublicKeyManifest,
+    FILTER_ENVELOPE_VERSION, FILTTER_VERSION,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Cmd {
+    #[command(subcommand)]
+    pub cmd: PipelineCommand,
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result<()> {
+        self.cmd.run()
+    }
+}
+
+/// Commands to run all steps from a csv file to a signed filter
+#[derive(clap::Subcommand, Debug)]
+pub enum PipelineCommand {
+    Run(Run),
+    Status(Status),
+}
+
+impl PipelineCommand {
+    pub fn run(&self) -> Result<()> {
+        match self {
+            Self::Run(cmd) => cmd.run(),
+            Self::Status(cmd) => cmd.run(),
+        }
+    }
+}
+
+/// Run all pending steps of a pipeline job
+///
+/// This generates the descriptor, signing data and manifest for the job in
+/// its work directory. The pipeline then pauses until the manifest in the work
+/// directory has collected the required number of signatures. Running the job
+/// again resumes where it left off and generates the signed filter once the
+/// manifest reaches the signature threshold.
+#[derive(Debug, clap::Args)]
+pub struct Run {
+    /// The job file (toml or json) describing the pipeline
+    #[arg(default_value = "job.toml")]
+    job: PathBuf,
+}
+
+impl Run {
+    pub fn run(&self) -> Result<()> {
+        let job =
+            Job::from_path(&self.job).context(format!("reading job {}", self.job.display()))?;
+        job.run()?;
+        print_json(&job.status()?)
+    }
+}
+
+/// Print the state of each step of a pipeline job
+#[derive(Debug, clap::Args)]
+pub struct Status {
+    /// The job file (toml or json) describing the pipeline
+    #[arg(default_value = "job.toml")]
+    job: PathBuf,
+}
+
+impl Status {
+    pub fn run(&self) -> Result<()> {
+        let job =
+            Job::from_path(&self.job).context(format!("reading job {}", self.job.display()))?;
+        print_json(&job.status()?)
+    }
+}
+
+/// A pipeline job file.
+///
+/// Relative paths in the job file are resolved against the directory the job
+/// file is in.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// The csv file to generate the filter from
+    csv: PathBuf,
+    /// The serial number of the filter
+    serial: u32,
+    /// The multisig public key file
+    #[serde(default = "Job::default_key")]
+    key: PathBuf,
+    /// The directory to generate all artifacts in
+    #[serde(default = "Job::default_work_dir")]
+    work_dir: PathBuf,
+    /// Whether members sign the signing envelope instead of the full signing
+    /// data
+    #[serde(default)]
+    envelope: bool,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepState {
+    Done,
+    Pending,
+    Waiting,
+}
+
+impl Job {
+    fn default_key() -> PathBuf {
+        "public_key.json".into()
+    }
+
+    fn default_work_dir() -> PathBuf {
+        "work".into()
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path)?;
+        let mut job: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&data)?,
+            _ => toml::from_str(&data)?,
+        };
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        job.csv = base.join(&job.csv);
+        job.key = base.join(&job.key);
+        job.work_dir = base.join(&job.work_dir);
+        Ok(job)
+    }
+
+    fn descriptor_path(&self) -> PathBuf {
+        self.work_dir.join("descriptor.bin.gz")
+    }
+
+    fn data_path(&self) -> PathBuf {
+        self.work_dir.join("data.bin")
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.work_dir.join("manifest.json")
+    }
+
+    fn filter_path(&self) -> PathBuf {
+        self.work_dir.join("filter.bin")
+    }
+
+    fn version(&self) -> u8 {
+        if self.envelope {
+            FILTER_ENVELOPE_VERSION
+        } else {
+            FILTTER_VERSION
+        }
+    }
+
+    fn key_manifest(&self) -> Result<PublicKeyManifest> {
+        read_public_key(&self.key)
+    }
+
+    /// Runs every step whose output is missing from the work directory.
+    /// Returns without generating a filter if the manifest does not have
+    /// enough signatures yet.
+    pub fn run(&self) -> Result<()> {
+        fs::create_dir_all(&self.work_dir).context(format!(
+            "creating work directory {}",
+            self.work_dir.display()
+        ))?;
+        let key_manifest = self.key_manifest()?;
+
+        let descriptor_path = self.descriptor_path();
+        if !descriptor_path.exists() {
+            let mut builder = DescriptorBuilder::default();
+            builder
+                .add_csv("", &self.csv)
+                .context(format!("reading csv {}", self.csv.display()))?;
+            builder.serial(self.serial);
+            let (descriptor, _) = builder.build()?;
+            write_atomic(&descriptor_path, |writer| Ok(descriptor.to_path(writer)?))?;
+        }
+
+        let data_path = self.data_path();
+        if !data_path.exists() {
+            let descriptor = Descriptor::from_path(&descriptor_path)
+                .context(format!("reading descriptor {}", descriptor_path.display()))?;
+            let filter = Filter::from_descriptor(self.serial, &descriptor)?;
+            let signing_bytes = filter.to_signing_bytes()?;
+            write_atomic(&data_path, |writer| Ok(writer.write_all(&signing_bytes)?))?;
+        }
+
+        let filter = Filter::from_signing_path(&data_path, self.version())
+            .context(format!("reading filter {}", data_path.display()))?;
+        if filter.serial != self.serial {
+            anyhow::bail!(
+                "signing data serial {} does not match job serial {}",
+                filter.serial,
+                self.serial
+            );
+        }
+
+        let manifest_path = self.manifest_path();
+        if !manifest_path.exists() {
+            let manifest = Manifest::for_filter(&filter, &key_manifest)?;
+            write_atomic(&manifest_path, |writer| {
+                Ok(serde_json::to_writer_pretty(writer, &manifest)?)
+            })?;
+        }
+        let manifest = self.manifest(&filter)?;
+
+        if !self.policy(&key_manifest, &manifest, &filter)?.satisfied {
+            return Ok(());
+        }
+
+        let filter_path = self.filter_path();
+        let key = key_manifest.public_key()?;
+        if !filter_path.exists() {
+            let mut filter = filter;
+            filter.signature = manifest.sign(&key_manifest)?;
+            filter
+                .verify_with_policy(&key_manifest)
+                .map_err(|_| Error::signature_invalid(&key))?;
+            let filter_bytes = filter.to_bytes()?;
+            write_atomic(&filter_path, |writer| Ok(writer.write_all(&filter_bytes)?))?;
+        }
+        Filter::from_path(&filter_path)
+            .context(format!("reading filter {}", filter_path.display()))?
+            .verify_with_policy(&key_manifest)
+            .map_err(|_| Error::signature_invalid(&key))?;
+        Ok(())
+    }
+
+    /// Reads the manifest from the work directory and checks that it matches
+    /// the given signing data.
+    fn manifest(&self, filter: &Filter) -> Result<Manifest> {
+        let manifest_path = self.manifest_path();
+        let manifest = Manifest::from_path(&manifest_path)
+            .context(format!("reading manifest {}", manifest_path.display()))?;
+        let filter_hash = base64_serde::encode(&filter.hash()?);
+        if manifest.hash != filter_hash {
+            return Err(Error::hash_mismatch(&manifest.hash, &filter_hash).into());
+        }
+        if manifest.version != filter.version {
+            anyhow::bail!(
+                "manifest version {} does not match job version {}",
+                manifest.version,
+                filter.version
+            );
+        }
+        Ok(manifest)
+    }
+
+    /// Evaluates the required signatures and signing policy against the
+    /// members that signed the manifest.
+    fn policy(
+        &self,
+        key_manifest: &PublicKeyManifest,
+        manifest: &Manifest,
+        filter: &Filter,
+    ) -> Result<PolicyVerify> {
+        let signing_bytes = filter.signed_message()?;
+        let signers = manifest.signers(key_manifest, &signing_bytes);
+        Ok(key_manifest.evaluate_policy(&signers))
+    }
+
+    /// Returns a summary of the state of every step in the job.
+    pub fn status(&self) -> Result<serde_json::Value> {
+        let state = |path: &Path| {
+            if path.exists() {
+                StepState::Done
+            } else {
+                StepState::Pending
+            }
+        };
+        let descriptor_path = self.descriptor_path();
+        let data_path = self.data_path();
+        let manifest_path = self.manifest_path();
+        let filter_path = self.filter_path();
+
+        let key_manifest = self.key_manifest()?;
+        let (signatures_state, policy) = if data_path.exists() && manifest_path.exists() {
+            let filter = Filter::from_signing_path(&data_path, self.version())
+                .context(format!("reading filter {}", data_path.display()))?;
+            let manifest = self.manifest(&filter)?;
+            let policy = self.policy(&key_manifest, &manifest, &filter)?;
+            if policy.satisfied {
+                (StepState::Done, Some(policy))
+            } else {
+                (StepState::Waiting, Some(policy))
+            }
+        } else {
+            (StepState::Pending, None)
+        };
+
+        let steps = json!([
+            { "step": "descriptor", "state": state(&descriptor_path), "path": descriptor_path },
+            { "step": "data", "state": state(&data_path), "path": data_path },
+            { "step": "manifest", "state": state(&manifest_path), "path": manifest_path },
+            {
+                "step": "signatures",
+                "state": signatures_state,
+                "path": manifest_path,
+                "policy": policy,
+            },
+            { "step": "filter", "state": state(&filter_path), "path": filter_path },
+        ]);
+        let next = steps
+            .as_array()
+            .and_then(|steps| {
+                steps
+                    .iter()
+                    .find(|step| step["state"] != json!(StepState::Done))
+                    .map(|step| step["step"].clone())
+            })
+            .unwrap_or_default();
+
+        Ok(json!({
+            "serial": self.serial,
+            "work_dir": self.work_dir,
+            "next": next,
+            "steps": steps,
+        }))
+    }
+}
+
+/// Writes a step output to a temporary file next to the given path and moves
+/// it into place once complete, so an interrupted step is never mistaken for a
+/// finished one.
+fn write_atomic<F>(path: &Path, f: F) -> Result<()>
+where
+    F: FnOnce(&mut fs::File) -> Result<()>,
+{
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut file =
+        fs::File::create(&partial).context(format!("opening output file {}", partial.display()))?;
+    f(&mut file)?;
+    file.sync_all()?;
+    fs::rename(&partial, path).context(format!("writing output file {}", path.display()))?;
+    Ok(())
+}
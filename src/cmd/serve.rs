@@ -0,0 +1,326 @@
+use crate::cmd::{error::ErrorCode, filter::filter_info, read_public_key};
+use anyhow::{anyhow, Context, Result};
+use helium_crypto::{PublicKey, PublicKeyBinary};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::Read,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+use xorf_generator::{Descriptor, Filter, FilterWatcher};
+
+/// The largest request body that is accepted, in bytes
+const MAX_BODY: u64 = 1 << 20;
+
+/// Serve filter membership and verification queries over HTTP
+///
+/// Loads and verifies the given filter and, optionally, a descriptor and
+/// answers queries about them as JSON. Newer filters and changes to the
+/// descriptor are picked up without a restart.
+#[derive(clap::Args, Debug)]
+pub struct Cmd {
+    /// The filter file, or a directory of filter files, to serve
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The public key to verify filters against
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+    /// The descriptor file to serve key lookups from
+    #[arg(long, short)]
+    descriptor: Option<PathBuf>,
+    /// The address to listen on
+    #[arg(long, short, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// How often, in seconds, to check for a new filter or descriptor
+    #[arg(long, default_value_t = 5)]
+    interval: u64,
+    /// The number of threads handling requests
+    #[arg(long, default_value_t = 4)]
+    threads: usize,
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result<()> {
+        let key_manifest = read_public_key(&self.key)?;
+        let interval = Duration::from_secs(self.interval);
+        let public_key = key_manifest.public_key()?;
+        let watcher = FilterWatcher::builder(&self.input, public_key.clone())
+            .interval(interval)
+            .on_accept(|path, filter| {
+                eprintln!("serving filter {} from {}", filter.serial, path.display())
+            })
+            .on_reject(|path, err| eprintln!("rejected filter {}: {err}", path.display()))
+            .start()
+            .context(format!("loading filter {}", self.input.display()))?;
+        let descriptor = self
+            .descriptor
+            .as_ref()
+            .map(|path| {
+                DescriptorFile::open(path, interval)
+                    .context(format!("reading descriptor {}", path.display()))
+            })
+            .transpose()?;
+
+        let server = Server::http(self.listen).map_err(|err| anyhow!(err))?;
+        eprintln!("listening on {}", self.listen);
+        let state = Arc::new(State {
+            server,
+            public_key,
+            watcher,
+            descriptor,
+            info: Mutex::default(),
+        });
+        let workers = (0..self.threads.max(1))
+            .map(|_| {
+                let state = state.clone();
+                thread::spawn(move || state.serve())
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            let _ = worker.join();
+        }
+        Ok(())
+    }
+}
+
+struct State {
+    server: Server,
+    public_key: PublicKey,
+    watcher: FilterWatcher,
+    descriptor: Option<DescriptorFile>,
+    /// The info of the filter it was last computed for
+    info: Mutex<Option<(Arc<Filter>, Value)>>,
+}
+
+/// A membership query in a batch request, either a public key or an edge
+/// between two public keys
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Query {
+    Key(PublicKeyBinary),
+    Edge(PublicKeyBinary, PublicKeyBinary),
+}
+
+#[derive(Debug, Serialize)]
+struct Contains {
+    address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    in_filter: bool,
+}
+
+impl Contains {
+    fn new(filter: &Filter, query: &Query) -> Self {
+        match query {
+            Query::Key(key) => Self {
+                address: key.to_string(),
+                target: None,
+                in_filter: filter.contains(key),
+            },
+            Query::Edge(source, target) => Self {
+                address: source.to_string(),
+                target: Some(target.to_string()),
+                in_filter: filter.contains_edge(source, target),
+            },
+        }
+    }
+}
+
+/// A failed request: the response status and the error to report
+struct HttpError(u16, anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for HttpError {
+    fn from(err: E) -> Self {
+        let err = err.into();
+        let status = match ErrorCode::from_error(&err) {
+            ErrorCode::Decode => 400,
+            _ => 500,
+        };
+        Self(status, err)
+    }
+}
+
+impl HttpError {
+    fn not_found(msg: &str) -> Self {
+        Self(404, anyhow!(msg.to_string()))
+    }
+
+    fn too_large() -> Self {
+        Self(413, anyhow!("request body is larger than {MAX_BODY} bytes"))
+    }
+
+    fn to_json(&self) -> Value {
+        let code = match self.0 {
+            400 => ErrorCode::Decode.as_str(),
+            404 => "not_found",
+            413 => "too_large",
+            _ => ErrorCode::from_error(&self.1).as_str(),
+        };
+        json!({
+            "error": {
+                "code": code,
+                "message": self.1.to_string(),
+            }
+        })
+    }
+}
+
+impl State {
+    fn serve(&self) {
+        for mut request in self.server.incoming_requests() {
+            let result = read_body(&mut request).and_then(|body| self.handle(&request, &body));
+            let (status, json) = match result {
+                Ok(json) => (200, json),
+                Err(err) => (err.0, err.to_json()),
+            };
+            let response = Response::from_string(json.to_string())
+                .with_status_code(status)
+                .with_header(
+                    Header::from_bytes("Content-Type", "application/json")
+                        .expect("valid content type header"),
+                );
+            if let Err(err) = request.respond(response) {
+                eprintln!("failed to respond: {err}");
+            }
+        }
+    }
+
+    fn handle(&self, request: &Request, body: &[u8]) -> Result<Value, HttpError> {
+        let path = request.url().split('?').next().unwrap_or_default();
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let filter = self.watcher.current();
+        match (request.method(), segments.as_slice()) {
+            (Method::Get, ["contains", key]) => {
+                let query = Query::Key(parse_key(key)?);
+                Ok(serde_json::to_value(Contains::new(&filter, &query))?)
+            }
+            (Method::Get, ["contains", source, target]) => {
+                let query = Query::Edge(parse_key(source)?, parse_key(target)?);
+                Ok(serde_json::to_value(Contains::new(&filter, &query))?)
+            }
+            (Method::Post, ["contains"]) => {
+                let queries: Vec<Query> = serde_json::from_slice(body)?;
+                let results = queries
+                    .iter()
+                    .map(|query| Contains::new(&filter, query))
+                    .collect::<Vec<_>>();
+                Ok(serde_json::to_value(results)?)
+            }
+            (Method::Get, ["info"]) => self.info(filter),
+            (Method::Get, ["descriptor", key]) => {
+                let descriptor = self
+                    .descriptor
+                    .as_ref()
+                    .ok_or_else(|| HttpError::not_found("no descriptor is being served"))?
+                    .current();
+                let key = parse_key(key)?;
+                let mut json = json!({});
+                if let Some(node) = descriptor.find_node(&key) {
+                    json["node"] = serde_json::to_value(node)?;
+                }
+                let edges = descriptor.find_edges(&key);
+                if !edges.is_empty() {
+                    json["edges"] = serde_json::to_value(edges)?;
+                }
+                Ok(json)
+            }
+            _ => Err(HttpError::not_found(&format!(
+                "no route for {} {path}",
+                request.method()
+            ))),
+        }
+    }
+
+    /// The info of the given filter, computed once per filter
+    fn info(&self, filter: Arc<Filter>) -> Result<Value, HttpError> {
+        let mut info = self.info.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some((cached, json)) = info.as_ref() {
+            if Arc::ptr_eq(cached, &filter) {
+                return Ok(json.clone());
+            }
+        }
+        // Only filters that verify against the public key are served
+        let mut json = filter_info(&filter)?;
+        json["address"] = self.public_key.to_string().into();
+        json["verified"] = true.into();
+        *info = Some((filter, json.clone()));
+        Ok(json)
+    }
+}
+
+/// Reads the request body, failing for bodies larger than [`MAX_BODY`]
+fn read_body(request: &mut Request) -> Result<Vec<u8>, HttpError> {
+    if request
+        .body_length()
+        .is_some_and(|len| len as u64 > MAX_BODY)
+    {
+        return Err(HttpError::too_large());
+    }
+    let mut body = vec![];
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(HttpError::too_large());
+    }
+    Ok(body)
+}
+
+fn parse_key(key: &str) -> Result<PublicKeyBinary, HttpError> {
+    key.parse()
+        .map_err(|err| HttpError(400, anyhow!("invalid public key {key}: {err}")))
+}
+
+/// A descriptor file that is reloaded in the background when it changes
+struct DescriptorFile {
+    descriptor: Arc<Mutex<Arc<Descriptor>>>,
+}
+
+impl DescriptorFile {
+    fn open(path: &Path, interval: Duration) -> Result<Self> {
+        let mut modified = fs::metadata(path)?.modified()?;
+        let descriptor = Arc::new(Mutex::new(Arc::new(Descriptor::from_path(path)?)));
+        let current = descriptor.clone();
+        let path = path.to_path_buf();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let Ok(changed) = fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
+                continue;
+            };
+            if changed == modified {
+                continue;
+            }
+            // A file that fails to load is retried when it changes again
+            modified = changed;
+            // The descriptor is decoded without holding the lock so lookups
+            // are served from the loaded descriptor in the meantime
+            match Descriptor::from_path(&path) {
+                Ok(descriptor) => {
+                    eprintln!("reloaded descriptor {}", path.display());
+                    *current.lock().unwrap_or_else(|err| err.into_inner()) = Arc::new(descriptor);
+                }
+                Err(err) => eprintln!("failed to reload descriptor {}: {err}", path.display()),
+            }
+        });
+        Ok(Self { descriptor })
+    }
+
+    /// The current descriptor. A descriptor that fails to reload keeps the
+    /// loaded descriptor in place.
+    fn current(&self) -> Arc<Descriptor> {
+        self.descriptor
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+}
@@ -1,12 +1,23 @@
-use crate::{edge_order, Result};
+use crate::{edge_order, Error, ReasonCodes, Result};
 use helium_crypto::PublicKeyBinary;
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 use prost::Message;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::File, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 include!(concat!(env!("OUT_DIR"), "/denylist.descriptor.rs"));
 
+/// The major schema version of descriptors written by this crate. Descriptors
+/// with a newer major version are refused.
+pub const DESCRIPTOR_MAJOR_VERSION: u32 = 1;
+/// The minor schema version of descriptors written by this crate
+pub const DESCRIPTOR_MINOR_VERSION: u32 = 0;
+
 #[derive(Debug, Deserialize)]
 struct CsvRow {
     pub public_key: PublicKeyBinary,
@@ -15,11 +26,20 @@ struct CsvRow {
     pub carryover: Option<u32>,
 }
 
+#[derive(Debug, Deserialize)]
+struct RemovalRow {
+    pub public_key: PublicKeyBinary,
+    pub target_key: Option<PublicKeyBinary>,
+    pub reason: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Eq)]
 pub struct FullNode {
     pub key: PublicKeyBinary,
     pub reason: Option<String>,
     pub carryover: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<String>,
 }
 
 impl PartialEq for FullNode {
@@ -52,6 +72,8 @@ impl From<FullNode> for Node {
             key: node.key.into(),
             reason: node.reason.unwrap_or_default(),
             carryover: node.carryover,
+            provenance: node.provenance.unwrap_or_default(),
+            reason_code: 0,
         }
     }
 }
@@ -62,6 +84,7 @@ impl From<Node> for FullNode {
             key: node.key.into(),
             reason: Some(node.reason),
             carryover: node.carryover,
+            provenance: non_empty(node.provenance),
         }
     }
 }
@@ -72,6 +95,8 @@ pub struct EdgeNode {
     target: PublicKeyBinary,
     reason: Option<String>,
     carryover: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provenance: Option<String>,
 }
 
 impl std::hash::Hash for EdgeNode {
@@ -111,23 +136,147 @@ impl EdgeNode {
             target,
             reason,
             carryover,
+            provenance: None,
         }
     }
 }
 
+/// Reads all descriptor data from the given reader, failing on empty input
+fn read_descriptor_data<R: std::io::Read>(mut reader: R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    if data.is_empty() {
+        return Err(Error::descriptor("Empty descriptor"));
+    }
+    Ok(data)
+}
+
 impl Descriptor {
     pub fn from_path(path: &Path) -> Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    /// Reads a gzip or zstd compressed, or uncompressed descriptor. The
+    /// compression is detected from the first bytes of the data. Uncompressed
+    /// data is only accepted if it decodes to a descriptor with a header, so
+    /// empty or unrecognized data is not mistaken for an empty descriptor.
+    pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Self> {
+        let data = read_descriptor_data(reader)?;
+        let compression = DescriptorCompression::detect(&data);
+        let descriptor = Self::decode_with(&data, compression)?;
+        if compression == DescriptorCompression::None && descriptor.header.is_none() {
+            return Err(Error::descriptor(
+                "Unrecognized descriptor: not compressed and without a header",
+            ));
+        }
+        Ok(descriptor)
+    }
+
+    /// Reads a descriptor with the given compression instead of detecting
+    /// it, which also reads uncompressed descriptors without a header.
+    pub fn from_reader_with_compression<R: std::io::Read>(
+        reader: R,
+        compression: DescriptorCompression,
+    ) -> Result<Self> {
+        let data = read_descriptor_data(reader)?;
+        Self::decode_with(&data, compression)
+    }
+
+    fn decode_with(data: &[u8], compression: DescriptorCompression) -> Result<Self> {
         use std::io::Read;
         let mut buf = Vec::new();
-        let mut reader = flate2::read::GzDecoder::new(File::open(path)?);
-        reader.read_to_end(&mut buf)?;
-        Ok(Self::decode(buf.as_ref())?)
+        let data = match compression {
+            DescriptorCompression::Gzip(_) => {
+                flate2::read::GzDecoder::new(data).read_to_end(&mut buf)?;
+                &buf
+            }
+            DescriptorCompression::Zstd(_) => {
+                zstd::stream::read::Decoder::new(data)?.read_to_end(&mut buf)?;
+                &buf
+            }
+            DescriptorCompression::None => data,
+        };
+        let descriptor = Self::decode(data)?;
+        descriptor.check_version()?;
+        descriptor.check_edges()?;
+        Ok(descriptor)
+    }
+
+    /// The major and minor schema version of the descriptor. Descriptors
+    /// without a header are version 0.0.
+    pub fn version(&self) -> (u32, u32) {
+        self.header
+            .as_ref()
+            .map(|header| (header.major_version, header.minor_version))
+            .unwrap_or((0, 0))
+    }
+
+    /// Checks that the schema version of the descriptor can be read
+    pub fn check_version(&self) -> Result {
+        let (major, minor) = self.version();
+        if major > DESCRIPTOR_MAJOR_VERSION {
+            return Err(Error::descriptor(&format!(
+                "Descriptor schema version {major}.{minor} is newer than supported version \
+                 {DESCRIPTOR_MAJOR_VERSION}.{DESCRIPTOR_MINOR_VERSION}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that every edge refers to keys in the edge key list, so the
+    /// edges of a descriptor that was read can be indexed without checks
+    fn check_edges(&self) -> Result {
+        let Some(edges) = &self.edges else {
+            return Ok(());
+        };
+        let keys = edges.keys.len();
+        if let Some(edge) = edges
+            .edges
+            .iter()
+            .find(|edge| edge.source as usize >= keys || edge.target as usize >= keys)
+        {
+            return Err(Error::descriptor(&format!(
+                "Edge {} -> {} refers to a key outside of the {keys} edge keys",
+                edge.source, edge.target
+            )));
+        }
+        Ok(())
+    }
+
+    /// The filter serial the descriptor is intended for, if any
+    pub fn serial(&self) -> Option<u32> {
+        self.header
+            .as_ref()
+            .map(|header| header.serial)
+            .filter(|serial| *serial != 0)
     }
 
     pub fn to_path<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        self.to_writer(writer, DescriptorCompression::default())
+    }
+
+    /// Writes the descriptor with the given compression
+    pub fn to_writer<W: std::io::Write>(
+        &self,
+        mut writer: W,
+        compression: DescriptorCompression,
+    ) -> Result<()> {
         use std::io::Write;
-        let mut file = flate2::write::GzEncoder::new(writer, flate2::Compression::best());
-        file.write_all(&self.encode_to_vec())?;
+        let data = self.encode_to_vec();
+        match compression {
+            DescriptorCompression::Gzip(level) => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(writer, flate2::Compression::new(level));
+                encoder.write_all(&data)?;
+                encoder.finish()?;
+            }
+            DescriptorCompression::Zstd(level) => {
+                let mut encoder = zstd::stream::write::Encoder::new(writer, level)?;
+                encoder.write_all(&data)?;
+                encoder.finish()?;
+            }
+            DescriptorCompression::None => writer.write_all(&data)?,
+        }
         Ok(())
     }
 
@@ -161,77 +310,60 @@ impl Descriptor {
     }
 
     pub fn from_csv(path: &Path) -> Result<Self> {
-        let mut rdr = csv::ReaderBuilder::new()
-            .has_headers(false)
-            .from_reader(File::open(path)?);
-        let mut full_nodes: IndexSet<FullNode> = IndexSet::new();
-        let mut edge_nodes: IndexSet<EdgeNode> = IndexSet::new();
-        let mut edge_keys: IndexSet<PublicKeyBinary> = IndexSet::new();
+        let mut builder = DescriptorBuilder::default();
+        builder.add_csv("", path)?;
+        Ok(builder.build()?.0)
+    }
 
-        for record in rdr.deserialize() {
-            let row: CsvRow = record?;
-            if let Some(target_key) = row.target_key {
-                // we enforce edge order here to dedupe two way edges.
-                let (source, target) = edge_order(&row.public_key, &target_key);
-                let edge = EdgeNode::new(
-                    source.clone(),
-                    target.clone(),
-                    row.reason,
-                    row.carryover.unwrap_or(0),
-                );
-                if !(full_nodes.contains(&FullNode {
-                    key: edge.source.clone(),
-                    reason: None,
-                    carryover: row.carryover.unwrap_or(0),
-                }) || full_nodes.contains(&FullNode {
-                    key: edge.target.clone(),
-                    reason: None,
-                    carryover: row.carryover.unwrap_or(0),
-                })) {
-                    edge_keys.insert(edge.source.clone());
-                    edge_keys.insert(edge.target.clone());
-                    edge_nodes.insert(edge);
-                }
+    /// The reason codes stored in the descriptor
+    pub fn reason_codes(&self) -> ReasonCodes {
+        ReasonCodes::from_reasons(&self.reasons)
+    }
+
+    /// The reason for a node or edge: the code for its reason code id if it
+    /// has one, and its free text reason otherwise. Ids that are not in the
+    /// reason code table of the descriptor are returned as `#<id>`.
+    pub fn reason(&self, reason: &str, reason_code: u32) -> String {
+        if reason_code == 0 {
+            return reason.to_string();
+        }
+        self.reasons
+            .iter()
+            .find(|entry| entry.id == reason_code)
+            .map(|entry| entry.code.clone())
+            .unwrap_or_else(|| format!("#{reason_code}"))
+    }
+
+    /// The number of nodes and edges per reason. Nodes and edges without a
+    /// reason are counted under `unspecified`.
+    pub fn reason_counts(&self) -> BTreeMap<String, ReasonCount> {
+        fn key(reason: String) -> String {
+            if reason.is_empty() {
+                "unspecified".to_string()
             } else {
-                full_nodes.insert(FullNode {
-                    key: row.public_key,
-                    reason: row.reason,
-                    carryover: row.carryover.unwrap_or(0),
-                });
+                reason
             }
         }
-
-        full_nodes.sort_unstable();
-        edge_nodes.sort_unstable();
-        let edges = edge_nodes
-            .into_iter()
-            .map(|node| {
-                let source = edge_keys.get_index_of(&node.source).unwrap() as u32;
-                let target = edge_keys.get_index_of(&node.target).unwrap() as u32;
-                Edge {
-                    source,
-                    target,
-                    reason: node.reason.unwrap_or_default(),
-                    carryover: node.carryover,
-                }
-            })
-            .collect();
-
-        Ok(Self {
-            nodes: full_nodes.into_iter().map(Into::into).collect(),
-            edges: Some(Edges {
-                keys: edge_keys.into_iter().map(Into::into).collect(),
-                edges,
-            }),
-        })
+        let mut counts: BTreeMap<String, ReasonCount> = BTreeMap::new();
+        for node in &self.nodes {
+            let reason = key(self.reason(&node.reason, node.reason_code));
+            counts.entry(reason).or_default().nodes += 1;
+        }
+        for edge in self.edges.iter().flat_map(|edges| &edges.edges) {
+            let reason = key(self.reason(&edge.reason, edge.reason_code));
+            counts.entry(reason).or_default().edges += 1;
+        }
+        counts
     }
 
     pub fn find_node(&self, key: &PublicKeyBinary) -> Option<FullNode> {
         self.nodes
             .iter()
             .find(|node| node.key.as_slice() == key.as_ref())
-            .cloned()
-            .map(Into::into)
+            .map(|node| FullNode {
+                reason: Some(self.reason(&node.reason, node.reason_code)),
+                ..node.clone().into()
+            })
     }
 
     pub fn find_edges(&self, key: &PublicKeyBinary) -> Vec<EdgeNode> {
@@ -250,12 +382,11 @@ impl Descriptor {
                         if edge.source == key_index || edge.target == key_index {
                             let source = edges.keys[edge.source as usize].clone().into();
                             let target = edges.keys[edge.target as usize].clone().into();
-                            let reason = if edge.reason.is_empty() {
-                                None
-                            } else {
-                                Some(edge.reason.clone())
-                            };
-                            Some(EdgeNode::new(source, target, reason, edge.carryover))
+                            let reason = non_empty(self.reason(&edge.reason, edge.reason_code));
+                            Some(EdgeNode {
+                                provenance: non_empty(edge.provenance.clone()),
+                                ..EdgeNode::new(source, target, reason, edge.carryover)
+                            })
                         } else {
                             None
                         }
@@ -269,3 +400,683 @@ impl Descriptor {
         }
     }
 }
+
+/// The compression of a descriptor file, with the compression level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorCompression {
+    None,
+    /// Gzip with a level from 0 to 9
+    Gzip(u32),
+    /// Zstd with a level from 1 to 22
+    Zstd(i32),
+}
+
+impl Default for DescriptorCompression {
+    fn default() -> Self {
+        Self::Gzip(9)
+    }
+}
+
+impl DescriptorCompression {
+    const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &'static [u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    /// Detects the compression of descriptor data from its first bytes.
+    /// The detected level is the default level of the compression.
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(Self::GZIP_MAGIC) {
+            Self::Gzip(9)
+        } else if data.starts_with(Self::ZSTD_MAGIC) {
+            Self::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)
+        } else {
+            Self::None
+        }
+    }
+}
+
+impl std::str::FromStr for DescriptorCompression {
+    type Err = Error;
+
+    /// Parses `none`, `gzip` or `zstd`, optionally followed by a level as
+    /// in `zstd:19`
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+        let invalid = || Error::descriptor(&format!("Invalid compression {s}"));
+        match (name, level) {
+            ("none", None) => Ok(Self::None),
+            ("gzip", None) => Ok(Self::default()),
+            ("gzip", Some(level)) => match level.parse() {
+                Ok(level) if level <= 9 => Ok(Self::Gzip(level)),
+                _ => Err(invalid()),
+            },
+            ("zstd", None) => Ok(Self::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            ("zstd", Some(level)) => match level.parse() {
+                Ok(level) if zstd::compression_level_range().contains(&level) => {
+                    Ok(Self::Zstd(level))
+                }
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The number of nodes and edges with a reason
+#[derive(Debug, Default, Serialize)]
+pub struct ReasonCount {
+    pub nodes: usize,
+    pub edges: usize,
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// The reason and carryover a source gives for a key or edge
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceEntry {
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub carryover: u32,
+}
+
+impl SourceEntry {
+    fn conflicts_with(&self, other: &Self) -> bool {
+        self.reason != other.reason || self.carryover != other.carryover
+    }
+}
+
+/// A key or edge that sources give a different reason or carryover for
+#[derive(Debug, Serialize)]
+pub struct SourceConflict {
+    pub key: PublicKeyBinary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<PublicKeyBinary>,
+    /// The entry of the source with the highest precedence
+    pub kept: SourceEntry,
+    /// The conflicting entries of the other sources
+    pub overridden: Vec<SourceEntry>,
+}
+
+/// The number of distinct nodes and edges read from a source
+#[derive(Debug, Serialize)]
+pub struct SourceSummary {
+    pub name: String,
+    pub nodes: usize,
+    pub edges: usize,
+}
+
+/// A key or edge that was removed by an allow override, with the sources
+/// that listed it and the number of nodes and edges that were removed
+#[derive(Debug, Serialize)]
+pub struct RemovalSummary {
+    pub key: PublicKeyBinary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<PublicKeyBinary>,
+    pub reason: String,
+    pub sources: Vec<String>,
+    pub nodes: usize,
+    pub edges: usize,
+}
+
+/// Describes how the sources of a descriptor were merged
+#[derive(Debug, Default, Serialize)]
+pub struct MergeReport {
+    pub sources: Vec<SourceSummary>,
+    pub conflicts: Vec<SourceConflict>,
+    pub removals: Vec<RemovalSummary>,
+    pub promotions: Vec<Promotion>,
+}
+
+/// When to promote an edge key to a full node. A key is promoted when it has
+/// more than `max_edges` edges, or when its edges make up at least
+/// `min_fraction` of its observed peers.
+#[derive(Debug, Clone)]
+pub struct PromotionRule {
+    pub max_edges: Option<usize>,
+    pub min_fraction: Option<f64>,
+    /// The number of peers observed for every key
+    pub observed_peers: HashMap<PublicKeyBinary, usize>,
+    /// The reason for promoted nodes
+    pub reason: String,
+}
+
+impl Default for PromotionRule {
+    fn default() -> Self {
+        Self {
+            max_edges: None,
+            min_fraction: None,
+            observed_peers: HashMap::new(),
+            reason: "promoted".to_string(),
+        }
+    }
+}
+
+impl PromotionRule {
+    /// Reads the observed peers from a csv file of public keys and peer
+    /// counts
+    pub fn read_observed_peers(&mut self, path: &Path) -> Result<&mut Self> {
+        self.read_observed_peers_reader(File::open(path)?)
+    }
+
+    /// Reads the observed peers as csv rows from the given reader
+    pub fn read_observed_peers_reader<R: std::io::Read>(&mut self, reader: R) -> Result<&mut Self> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(reader);
+        for record in rdr.deserialize() {
+            let (key, peers): (PublicKeyBinary, usize) = record?;
+            self.observed_peers.insert(key, peers);
+        }
+        Ok(self)
+    }
+
+    fn promotes(&self, key: &PublicKeyBinary, edges: usize) -> Option<Option<usize>> {
+        let observed = self.observed_peers.get(key).copied();
+        let by_edges = self.max_edges.is_some_and(|max_edges| edges > max_edges);
+        let by_fraction = match (self.min_fraction, observed) {
+            (Some(fraction), Some(peers)) if peers > 0 => edges as f64 / peers as f64 >= fraction,
+            _ => false,
+        };
+        (by_edges || by_fraction).then_some(observed)
+    }
+}
+
+/// An edge key that was promoted to a full node, with the number of its
+/// edges that were pruned
+#[derive(Debug, Serialize)]
+pub struct Promotion {
+    pub key: PublicKeyBinary,
+    pub edges: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed_peers: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Source {
+    name: String,
+    nodes: IndexMap<PublicKeyBinary, SourceEntry>,
+    edges: IndexMap<(PublicKeyBinary, PublicKeyBinary), SourceEntry>,
+    /// The row of the first entry for every key or edge, counted over all
+    /// sources in the order they were added
+    rows: HashMap<(PublicKeyBinary, Option<PublicKeyBinary>), usize>,
+}
+
+/// Builds a descriptor from one or more named csv sources.
+///
+/// Every node and edge is tagged with the name of the source it was taken
+/// from. When sources give a different reason or carryover for the same key
+/// or edge, the entry of the source with the highest precedence is kept and
+/// the conflict is reported. Sources listed in the precedence come first, in
+/// that order, followed by the remaining sources in the order they were added.
+///
+/// An edge is pruned when a full node for one of its keys is listed in a row
+/// before it, counting rows over all sources in the order they were added.
+/// This is the same as reading the sources concatenated into one csv file.
+///
+/// Keys and edges in the allow overrides are removed from the descriptor even
+/// if sources include them, and every removal is reported.
+#[derive(Debug, Default)]
+pub struct DescriptorBuilder {
+    sources: Vec<Source>,
+    rows: usize,
+    precedence: Vec<String>,
+    /// The reason for every removed key or edge
+    removals: IndexMap<(PublicKeyBinary, Option<PublicKeyBinary>), String>,
+    reason_codes: Option<ReasonCodes>,
+    promotion: Option<PromotionRule>,
+    serial: u32,
+}
+
+impl DescriptorBuilder {
+    /// Sets the source names in order of decreasing precedence
+    pub fn precedence<S: AsRef<str>>(&mut self, names: &[S]) -> &mut Self {
+        self.precedence = names.iter().map(|name| name.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Requires the reason of every node and edge to be a code in the given
+    /// registry. Reasons are then stored as reason code ids, along with the
+    /// reason code table.
+    pub fn reason_codes(&mut self, codes: ReasonCodes) -> &mut Self {
+        self.reason_codes = Some(codes);
+        self
+    }
+
+    /// Sets the filter serial the descriptor is intended for
+    pub fn serial(&mut self, serial: u32) -> &mut Self {
+        self.serial = serial;
+        self
+    }
+
+    /// Promotes edge keys to full nodes by the given rule. The edges of a
+    /// promoted key are pruned since the full node covers them.
+    pub fn promotion(&mut self, rule: PromotionRule) -> &mut Self {
+        self.promotion = Some(rule);
+        self
+    }
+
+    /// Adds the csv file at the given path as a source with the given name
+    pub fn add_csv(&mut self, name: &str, path: &Path) -> Result<&mut Self> {
+        self.add_reader(name, File::open(path)?)
+    }
+
+    /// Adds csv rows from the given reader as a source with the given name
+    pub fn add_reader<R: std::io::Read>(&mut self, name: &str, reader: R) -> Result<&mut Self> {
+        if self.sources.iter().any(|source| source.name == name) {
+            return Err(Error::descriptor(&format!("Duplicate source {name}")));
+        }
+        let mut source = Source {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(reader);
+        for record in rdr.deserialize() {
+            let row: CsvRow = record?;
+            let index = self.rows;
+            self.rows += 1;
+            let entry = SourceEntry {
+                source: name.to_string(),
+                reason: row.reason,
+                carryover: row.carryover.unwrap_or(0),
+            };
+            // The first row for a key or edge in a source wins
+            if let Some(target_key) = row.target_key {
+                // we enforce edge order here to dedupe two way edges.
+                let (source_key, target_key) = edge_order(&row.public_key, &target_key);
+                source
+                    .rows
+                    .entry((source_key.clone(), Some(target_key.clone())))
+                    .or_insert(index);
+                source
+                    .edges
+                    .entry((source_key.clone(), target_key.clone()))
+                    .or_insert(entry);
+            } else {
+                source
+                    .rows
+                    .entry((row.public_key.clone(), None))
+                    .or_insert(index);
+                source.nodes.entry(row.public_key).or_insert(entry);
+            }
+        }
+        self.sources.push(source);
+        Ok(self)
+    }
+
+    /// Adds the allow overrides in the csv file at the given path
+    pub fn add_removals(&mut self, path: &Path) -> Result<&mut Self> {
+        self.add_removals_reader(File::open(path)?)
+    }
+
+    /// Adds allow overrides from the given reader. Every row is a public key
+    /// or an edge to remove, followed by the reason for the removal.
+    pub fn add_removals_reader<R: std::io::Read>(&mut self, reader: R) -> Result<&mut Self> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(reader);
+        for record in rdr.deserialize() {
+            let row: RemovalRow = record?;
+            if row.reason.trim().is_empty() {
+                return Err(Error::descriptor(&format!(
+                    "Removal of {} has no reason",
+                    row.public_key
+                )));
+            }
+            let key = match row.target_key {
+                Some(target_key) => {
+                    let (source, target) = edge_order(&row.public_key, &target_key);
+                    (source.clone(), Some(target.clone()))
+                }
+                None => (row.public_key, None),
+            };
+            self.removals.entry(key).or_insert(row.reason);
+        }
+        Ok(self)
+    }
+
+    /// Merges the sources into a descriptor and reports how they were merged
+    pub fn build(self) -> Result<(Descriptor, MergeReport)> {
+        for name in &self.precedence {
+            if !self.sources.iter().any(|source| &source.name == name) {
+                return Err(Error::descriptor(&format!(
+                    "Unknown source {name} in precedence"
+                )));
+            }
+        }
+        if let Some(codes) = &self.reason_codes {
+            self.check_reason_codes(codes)?;
+            if let Some(rule) = &self.promotion {
+                if codes.id(&rule.reason).is_none() {
+                    return Err(Error::descriptor(&format!(
+                        "Unknown reason code {} for promotions",
+                        rule.reason
+                    )));
+                }
+            }
+        }
+        let rank = |name: &str| {
+            self.precedence
+                .iter()
+                .position(|entry| entry == name)
+                .unwrap_or(self.precedence.len())
+        };
+        let mut sources = self.sources.iter().collect::<Vec<_>>();
+        // A stable sort keeps unlisted sources in the order they were added
+        sources.sort_by_key(|source| rank(&source.name));
+
+        let mut report = MergeReport {
+            sources: self
+                .sources
+                .iter()
+                .map(|source| SourceSummary {
+                    name: source.name.clone(),
+                    nodes: source.nodes.len(),
+                    edges: source.edges.len(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut nodes = merge(
+            &self.sources,
+            &sources,
+            |source| &source.nodes,
+            |key, kept, overridden| {
+                report.conflicts.push(SourceConflict {
+                    key: key.clone(),
+                    target: None,
+                    kept: kept.clone(),
+                    overridden,
+                })
+            },
+        );
+        let mut edges = merge(
+            &self.sources,
+            &sources,
+            |source| &source.edges,
+            |(key, target), kept, overridden| {
+                report.conflicts.push(SourceConflict {
+                    key: key.clone(),
+                    target: Some(target.clone()),
+                    kept: kept.clone(),
+                    overridden,
+                })
+            },
+        );
+        report.removals = self.remove(&mut nodes, &mut edges);
+        report.promotions = self.promote(&mut nodes, &edges);
+
+        let mut full_nodes = nodes
+            .into_iter()
+            .map(|(key, entry)| FullNode {
+                key,
+                reason: entry.reason,
+                carryover: entry.carryover,
+                provenance: Some(entry.source),
+            })
+            .collect::<IndexSet<_>>();
+        let full_keys = full_nodes
+            .iter()
+            .map(|node| node.key.clone())
+            .collect::<HashSet<_>>();
+        // edges of full nodes listed before them are already covered by the
+        // full node. Promoted nodes cover all of their edges.
+        let covers = |key: &PublicKeyBinary, edge_row: usize| {
+            full_keys.contains(key)
+                && self
+                    .first_row(&(key.clone(), None))
+                    .is_none_or(|row| row < edge_row)
+        };
+        let mut edge_nodes = edges
+            .into_iter()
+            .filter(|((source, target), _)| {
+                let edge_row = self
+                    .first_row(&(source.clone(), Some(target.clone())))
+                    .unwrap_or(0);
+                !(covers(source, edge_row) || covers(target, edge_row))
+            })
+            .map(|((source, target), entry)| EdgeNode {
+                provenance: Some(entry.source),
+                ..EdgeNode::new(source, target, entry.reason, entry.carryover)
+            })
+            .collect::<IndexSet<_>>();
+        let mut edge_keys: IndexSet<PublicKeyBinary> = IndexSet::new();
+        for edge in &edge_nodes {
+            edge_keys.insert(edge.source.clone());
+            edge_keys.insert(edge.target.clone());
+        }
+
+        full_nodes.sort_unstable();
+        edge_nodes.sort_unstable();
+        let edges = edge_nodes
+            .into_iter()
+            .map(|node| {
+                let source = edge_keys.get_index_of(&node.source).unwrap() as u32;
+                let target = edge_keys.get_index_of(&node.target).unwrap() as u32;
+                let (reason, reason_code) = self.encode_reason(node.reason);
+                Edge {
+                    source,
+                    target,
+                    reason,
+                    carryover: node.carryover,
+                    provenance: node.provenance.unwrap_or_default(),
+                    reason_code,
+                }
+            })
+            .collect();
+        let nodes = full_nodes
+            .into_iter()
+            .map(|node| {
+                let (reason, reason_code) = self.encode_reason(node.reason.clone());
+                Node {
+                    reason,
+                    reason_code,
+                    ..node.into()
+                }
+            })
+            .collect();
+
+        let descriptor = Descriptor {
+            nodes,
+            edges: Some(Edges {
+                keys: edge_keys.into_iter().map(Into::into).collect(),
+                edges,
+            }),
+            reasons: self
+                .reason_codes
+                .as_ref()
+                .map(ReasonCodes::to_reasons)
+                .unwrap_or_default(),
+            header: Some(Header {
+                major_version: DESCRIPTOR_MAJOR_VERSION,
+                minor_version: DESCRIPTOR_MINOR_VERSION,
+                created_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_secs())
+                    .unwrap_or(0),
+                generator_version: env!("CARGO_PKG_VERSION").to_string(),
+                serial: self.serial,
+            }),
+        };
+        Ok((descriptor, report))
+    }
+
+    /// Adds a full node for every edge key that the promotion rule promotes.
+    /// All edges of promoted keys are pruned.
+    fn promote(
+        &self,
+        nodes: &mut IndexMap<PublicKeyBinary, SourceEntry>,
+        edges: &IndexMap<(PublicKeyBinary, PublicKeyBinary), SourceEntry>,
+    ) -> Vec<Promotion> {
+        let Some(rule) = &self.promotion else {
+            return vec![];
+        };
+        // The number of edges and the lowest carryover of the edges of every
+        // edge key that is not a full node yet
+        let mut degrees: IndexMap<&PublicKeyBinary, (usize, u32)> = IndexMap::new();
+        for ((source, target), entry) in edges {
+            for key in [source, target] {
+                if !nodes.contains_key(key) {
+                    let (count, carryover) = degrees.entry(key).or_insert((0, entry.carryover));
+                    *count += 1;
+                    *carryover = (*carryover).min(entry.carryover);
+                }
+            }
+        }
+        let mut promotions = vec![];
+        for (key, (count, carryover)) in degrees {
+            if let Some(observed_peers) = rule.promotes(key, count) {
+                nodes.insert(
+                    key.clone(),
+                    SourceEntry {
+                        source: "promotion".to_string(),
+                        reason: Some(rule.reason.clone()),
+                        carryover,
+                    },
+                );
+                promotions.push(Promotion {
+                    key: key.clone(),
+                    edges: count,
+                    observed_peers,
+                });
+            }
+        }
+        promotions
+    }
+
+    /// The first row of a key or edge in any source
+    fn first_row(&self, key: &(PublicKeyBinary, Option<PublicKeyBinary>)) -> Option<usize> {
+        self.sources
+            .iter()
+            .filter_map(|source| source.rows.get(key).copied())
+            .min()
+    }
+
+    /// Checks that every reason in every source is a known reason code
+    fn check_reason_codes(&self, codes: &ReasonCodes) -> Result {
+        for source in &self.sources {
+            let entries = source
+                .nodes
+                .iter()
+                .chain(source.edges.iter().map(|((key, _), entry)| (key, entry)));
+            for (key, entry) in entries {
+                match entry.reason.as_deref() {
+                    Some(reason) if !reason.is_empty() && codes.id(reason).is_none() => {
+                        return Err(Error::descriptor(&format!(
+                            "Unknown reason code {reason} for {key} in source {}",
+                            source.name
+                        )))
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The free text reason and reason code id to store for a reason
+    fn encode_reason(&self, reason: Option<String>) -> (String, u32) {
+        let reason = reason.unwrap_or_default();
+        match &self.reason_codes {
+            Some(codes) if !reason.is_empty() => (String::new(), codes.id(&reason).unwrap_or(0)),
+            _ => (reason, 0),
+        }
+    }
+
+    /// Removes the keys and edges of the allow overrides from the merged
+    /// nodes and edges. Removing a key removes its full node and all of its
+    /// edges.
+    fn remove(
+        &self,
+        nodes: &mut IndexMap<PublicKeyBinary, SourceEntry>,
+        edges: &mut IndexMap<(PublicKeyBinary, PublicKeyBinary), SourceEntry>,
+    ) -> Vec<RemovalSummary> {
+        self.removals
+            .iter()
+            .map(|((key, target), reason)| {
+                let (removed_nodes, removed_edges, sources) = if let Some(target) = target {
+                    let edge = (key.clone(), target.clone());
+                    let sources = self
+                        .sources
+                        .iter()
+                        .filter(|source| source.edges.contains_key(&edge))
+                        .map(|source| source.name.clone())
+                        .collect();
+                    (0, edges.shift_remove(&edge).map_or(0, |_| 1), sources)
+                } else {
+                    let sources = self
+                        .sources
+                        .iter()
+                        .filter(|source| {
+                            source.nodes.contains_key(key)
+                                || source
+                                    .edges
+                                    .keys()
+                                    .any(|(source, target)| source == key || target == key)
+                        })
+                        .map(|source| source.name.clone())
+                        .collect();
+                    let removed_nodes = nodes.shift_remove(key).map_or(0, |_| 1);
+                    let count = edges.len();
+                    edges.retain(|(source, target), _| source != key && target != key);
+                    (removed_nodes, count - edges.len(), sources)
+                };
+                RemovalSummary {
+                    key: key.clone(),
+                    target: target.clone(),
+                    reason: reason.clone(),
+                    sources,
+                    nodes: removed_nodes,
+                    edges: removed_edges,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Merges the entries of all sources, in the order they first appear in the
+/// given sources. The entry of the first source in the ranked sources wins
+/// and the conflicting entries of other sources are passed to the given
+/// conflict handler.
+fn merge<'a, K, E, C>(
+    sources: &'a [Source],
+    ranked: &[&'a Source],
+    entries: E,
+    mut conflict: C,
+) -> IndexMap<K, SourceEntry>
+where
+    K: std::hash::Hash + Eq + Clone + 'a,
+    E: Fn(&'a Source) -> &'a IndexMap<K, SourceEntry>,
+    C: FnMut(&K, &SourceEntry, Vec<SourceEntry>),
+{
+    let mut merged = IndexMap::new();
+    for key in sources.iter().flat_map(|source| entries(source).keys()) {
+        if merged.contains_key(key) {
+            continue;
+        }
+        let mut candidates = ranked.iter().filter_map(|source| entries(source).get(key));
+        let Some(kept) = candidates.next() else {
+            continue;
+        };
+        let overridden = candidates
+            .filter(|entry| entry.conflicts_with(kept))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !overridden.is_empty() {
+            conflict(key, kept, overridden);
+        }
+        merged.insert(key.clone(), kept.clone());
+    }
+    merged
+}
@@ -1,14 +1,15 @@
-use crate::{base64_serde, Descriptor, Error, Result};
+use crate::{
+    base64_serde, verify, verify_chain, Descriptor, Error, KeyRotation, PolicyVerify,
+    PublicKeyManifest, Result, FILTER_ENVELOPE_CONTEXT, FILTER_ENVELOPE_VERSION, FILTTER_VERSION,
+};
 use bytes::{Buf, BufMut, BytesMut};
-use helium_crypto::{PublicKey, PublicKeyBinary, Verify};
+use helium_crypto::{PublicKey, PublicKeyBinary};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::{fs::File, hash::Hasher, io::Read, path::Path};
-use twox_hash::XxHash64;
+use std::{fmt, fs::File, io::Read, path::Path, str::FromStr};
 use xorf::{BinaryFuse32, Filter as _, Xor32};
 
-pub const FILTTER_VERSION: u8 = 2;
-
 #[derive(Serialize)]
 pub struct Filter {
     pub version: u8,
@@ -37,6 +38,44 @@ impl From<BinaryFuse32> for FilterData {
     }
 }
 
+/// The kind of filter to construct from a descriptor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterKind {
+    #[default]
+    Xor,
+    BinaryFuse,
+}
+
+impl FromStr for FilterKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "xor" => Ok(Self::Xor),
+            "binary_fuse" => Ok(Self::BinaryFuse),
+            _ => Err(Error::filter(&format!("Unknown filter kind {s}"))),
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Xor => "xor",
+            Self::BinaryFuse => "binary_fuse",
+        })
+    }
+}
+
+impl From<&FilterData> for FilterKind {
+    fn from(filter: &FilterData) -> Self {
+        match filter {
+            FilterData::Xor(_) => Self::Xor,
+            FilterData::BFuse(_) => Self::BinaryFuse,
+        }
+    }
+}
+
 impl FilterData {
     pub fn contains(&self, hash: &u64) -> bool {
         match self {
@@ -52,6 +91,64 @@ impl FilterData {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Xor(_) => "xor",
+            Self::BFuse(_) => "binary_fuse",
+        }
+    }
+
+    /// The width of a single fingerprint in bits
+    pub fn fingerprint_bits(&self) -> u32 {
+        match self {
+            Self::Xor(_) | Self::BFuse(_) => u32::BITS,
+        }
+    }
+
+    /// The theoretical false positive rate for the filter kind and
+    /// fingerprint width.
+    pub fn false_positive_rate(&self) -> f64 {
+        2f64.powi(-(self.fingerprint_bits() as i32))
+    }
+
+    /// Estimates the number of entries the filter was constructed from.
+    ///
+    /// The entry count is not stored in the filter, so this inverts the
+    /// capacity overhead each filter kind uses during construction.
+    pub fn estimated_entries(&self) -> usize {
+        let len = self.len() as f64;
+        let entries = match self {
+            Self::Xor(_) => (len - 32.0) / 1.23,
+            Self::BFuse(_) => len / 1.125,
+        };
+        entries.max(0.0).round() as usize
+    }
+
+    /// A short encoding of the filter kind, fingerprint width and number of
+    /// fingerprints, hashed into the signing envelope.
+    pub fn metadata(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        buf.put_u8(match self {
+            Self::Xor(_) => 0,
+            Self::BFuse(_) => 1,
+        });
+        buf.put_u32_le(self.fingerprint_bits());
+        buf.put_u64_le(self.len() as u64);
+        buf.to_vec()
+    }
+
+    pub fn bits_per_entry(&self) -> f64 {
+        let entries = self.estimated_entries();
+        if entries == 0 {
+            return 0.0;
+        }
+        (self.len() as f64 * self.fingerprint_bits() as f64) / entries as f64
+    }
+
     pub fn to_signing_bytes(&self, version: u8) -> Result<Vec<u8>> {
         match version {
             1 => {
@@ -61,7 +158,7 @@ impl FilterData {
                     Err(Error::filter("Unsupported filter version"))
                 }
             }
-            2 => Ok(bincode::serialize(self)?),
+            2 | FILTER_ENVELOPE_VERSION => Ok(bincode::serialize(self)?),
             _ => Err(Error::filter("Unsupported filter version")),
         }
     }
@@ -72,7 +169,7 @@ impl FilterData {
                 let filter: Xor32 = bincode::deserialize(data)?;
                 Ok(Self::Xor(filter))
             }
-            2 => {
+            2 | FILTER_ENVELOPE_VERSION => {
                 let filter: Self = bincode::deserialize(data)?;
                 Ok(filter)
             }
@@ -101,6 +198,23 @@ impl Filter {
     }
 
     pub fn from_descriptor(serial: u32, descriptor: &Descriptor) -> Result<Self> {
+        Self::from_descriptor_with_kind(serial, descriptor, FilterKind::Xor)
+    }
+
+    /// Constructs a filter of the given kind for the nodes and edges of a
+    /// descriptor
+    pub fn from_descriptor_with_kind(
+        serial: u32,
+        descriptor: &Descriptor,
+        kind: FilterKind,
+    ) -> Result<Self> {
+        if let Some(descriptor_serial) = descriptor.serial() {
+            if descriptor_serial != serial {
+                return Err(Error::filter(&format!(
+                    "Descriptor is for serial {descriptor_serial}, not {serial}"
+                )));
+            }
+        }
         let mut hashes: Vec<u64> = Vec::new();
 
         for node in &descriptor.nodes {
@@ -116,8 +230,13 @@ impl Filter {
         }
         hashes.sort_unstable();
         hashes.dedup();
-        let filter = Xor32::from(&hashes);
-        Filter::new(serial, filter)
+        match kind {
+            FilterKind::Xor => Filter::new(serial, Xor32::from(&hashes)),
+            FilterKind::BinaryFuse => {
+                let filter = BinaryFuse32::try_from(&hashes).map_err(Error::filter)?;
+                Filter::new(serial, filter)
+            }
+        }
     }
 
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
@@ -151,12 +270,93 @@ impl Filter {
         self.filter.contains(&edge_hash(source, target))
     }
 
+    /// Checks the given number of random ed25519 public keys against the
+    /// filter and returns how many of them were (falsely) reported as
+    /// members.
+    pub fn sample_false_positives(&self, samples: usize) -> usize {
+        let mut rng = rand::thread_rng();
+        let mut key = [0u8; 33];
+        // mainnet ed25519 key tag
+        key[0] = 0x01;
+        (0..samples)
+            .filter(|_| {
+                rng.fill(&mut key[1..]);
+                self.contains(&PublicKeyBinary::from(&key[..]))
+            })
+            .count()
+    }
+
+    /// Returns the message the filter signature is over. This is the signing
+    /// envelope for envelope versioned filters, and the full signing data for
+    /// earlier versions.
+    pub fn signed_message(&self) -> Result<Vec<u8>> {
+        match self.version {
+            FILTER_ENVELOPE_VERSION => self.to_envelope_bytes(),
+            _ => self.to_signing_bytes(),
+        }
+    }
+
+    /// Returns the sha256 hash of the message members sign for the filter.
+    /// Attestations are made over this hash.
+    pub fn signed_message_hash(&self) -> Result<Vec<u8>> {
+        Ok(Sha256::digest(self.signed_message()?).to_vec())
+    }
+
+    /// Returns the signing envelope for the filter: the envelope context, the
+    /// filter version, the serial as a little endian u32, the sha256 hash of
+    /// the encoded filter data and the sha256 hash of the filter metadata.
+    pub fn to_envelope_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(FILTER_ENVELOPE_CONTEXT);
+        buf.put_u8(self.version);
+        buf.put_u32_le(self.serial);
+        buf.extend_from_slice(&Sha256::digest(self.filter.to_signing_bytes(self.version)?));
+        buf.extend_from_slice(&Sha256::digest(self.filter.metadata()));
+        Ok(buf.to_vec())
+    }
+
+    /// Verifies the filter signature against the given public key with the
+    /// same rules as the no_std [`verify`] module.
     pub fn verify(&self, public_key: &PublicKey) -> Result {
-        let msg = self.to_signing_bytes()?;
-        public_key.verify(&msg, &self.signature)?;
+        let msg = self.signed_message()?;
+        verify::verify_signature(&public_key.to_vec(), &msg, &self.signature)?;
         Ok(())
     }
 
+    /// Verifies the filter against the multisig key of the given key manifest
+    /// and, if the key manifest has a signing policy, checks that the members
+    /// that signed the filter satisfy that policy.
+    pub fn verify_with_policy(&self, key_manifest: &PublicKeyManifest) -> Result<PolicyVerify> {
+        let public_key = key_manifest.public_key()?;
+        self.verify(&public_key)?;
+        let msg = self.signed_message()?;
+        let key = public_key.to_vec();
+        let signers = verify::MultisigSigners::new(&key, &msg, &self.signature)?
+            .iter()
+            .map(PublicKey::from_bytes)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let policy = key_manifest.evaluate_policy(&signers);
+        if !policy.satisfied {
+            return Err(Error::policy(
+                "Filter signers do not satisfy signing policy",
+            ));
+        }
+        Ok(policy)
+    }
+
+    /// Verifies the filter against the key at the end of the given chain of
+    /// key rotations, starting at the given pinned root key. The signing
+    /// policy of the key manifest the chain ends at is enforced as with
+    /// [`Filter::verify_with_policy`]. Without rotations only the threshold
+    /// of the root key is checked.
+    pub fn verify_with_chain(&self, root: &PublicKey, chain: &[KeyRotation]) -> Result {
+        verify_chain(root, chain)?;
+        match chain.last() {
+            Some(rotation) => self.verify_with_policy(&rotation.to).map(|_| ()),
+            None => self.verify(root),
+        }
+    }
+
     pub fn to_signing_bytes(&self) -> Result<Vec<u8>> {
         let mut buf = BytesMut::new();
         buf.put_u32_le(self.serial);
@@ -167,6 +367,9 @@ impl Filter {
 
     pub fn from_signing_bytes(data: &[u8], version: u8) -> Result<Self> {
         let mut buf = data;
+        if buf.remaining() < 4 {
+            return Err(Error::filter("Filter data too short"));
+        }
         let serial = buf.get_u32_le();
         let filter_data = FilterData::from_signing_bytes(buf, version)?;
         Ok(Self {
@@ -179,8 +382,14 @@ impl Filter {
 
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
         let mut buf = data;
+        if buf.remaining() < 3 {
+            return Err(Error::filter("Filter data too short"));
+        }
         let version = buf.get_u8();
         let signature_len = buf.get_u16_le() as usize;
+        if buf.remaining() < signature_len {
+            return Err(Error::filter("Filter data too short"));
+        }
         let signature = buf.copy_to_bytes(signature_len).to_vec();
         let mut filter = Self::from_signing_bytes(buf, version)?;
         filter.signature = signature;
@@ -199,9 +408,7 @@ impl Filter {
 }
 
 pub fn public_key_hash(public_key: &PublicKeyBinary) -> u64 {
-    let mut hasher = XxHash64::default();
-    hasher.write(public_key.as_ref());
-    hasher.finish()
+    verify::public_key_hash(public_key.as_ref())
 }
 
 pub fn edge_order<'a>(
@@ -216,9 +423,5 @@ pub fn edge_order<'a>(
 }
 
 pub fn edge_hash(a: &PublicKeyBinary, b: &PublicKeyBinary) -> u64 {
-    let (a, b) = edge_order(a, b);
-    let mut hasher = XxHash64::default();
-    hasher.write(a.as_ref());
-    hasher.write(b.as_ref());
-    hasher.finish()
+    verify::edge_hash(a.as_ref(), b.as_ref())
 }
@@ -0,0 +1,205 @@
+use crate::{Descriptor, Error, Result};
+use helium_crypto::PublicKeyBinary;
+use std::{
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    io::Write,
+};
+
+/// The edges of a descriptor as a graph of edge keys, for export to graph
+/// tools.
+pub struct DescriptorGraph {
+    keys: Vec<PublicKeyBinary>,
+    edges: Vec<GraphEdge>,
+}
+
+struct GraphEdge {
+    source: usize,
+    target: usize,
+    reason: String,
+    carryover: u32,
+    provenance: String,
+}
+
+impl DescriptorGraph {
+    pub fn new(descriptor: &Descriptor) -> Self {
+        let Some(edges) = &descriptor.edges else {
+            return Self {
+                keys: vec![],
+                edges: vec![],
+            };
+        };
+        Self {
+            keys: edges
+                .keys
+                .iter()
+                .map(|key| PublicKeyBinary::from(key.as_slice()))
+                .collect(),
+            edges: edges
+                .edges
+                .iter()
+                .map(|edge| GraphEdge {
+                    source: edge.source as usize,
+                    target: edge.target as usize,
+                    reason: descriptor.reason(&edge.reason, edge.reason_code),
+                    carryover: edge.carryover,
+                    provenance: edge.provenance.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The subgraph of the keys within the given number of edges of the given
+    /// key, with all edges between them
+    pub fn neighborhood(self, key: &PublicKeyBinary, hops: usize) -> Result<Self> {
+        let start = self
+            .keys
+            .iter()
+            .position(|entry| entry == key)
+            .ok_or_else(|| Error::descriptor(&format!("Key {key} is not in any edge")))?;
+        let mut adjacent = vec![vec![]; self.keys.len()];
+        for edge in &self.edges {
+            adjacent[edge.source].push(edge.target);
+            adjacent[edge.target].push(edge.source);
+        }
+
+        // Breadth first, mapping every reached key to its index in the
+        // subgraph
+        let mut reached = HashMap::from([(start, 0)]);
+        let mut order = vec![start];
+        let mut queue = VecDeque::from([(start, 0)]);
+        while let Some((index, distance)) = queue.pop_front() {
+            if distance == hops {
+                continue;
+            }
+            for &next in &adjacent[index] {
+                if let Entry::Vacant(entry) = reached.entry(next) {
+                    entry.insert(order.len());
+                    order.push(next);
+                    queue.push_back((next, distance + 1));
+                }
+            }
+        }
+
+        let keys = order
+            .iter()
+            .map(|&index| self.keys[index].clone())
+            .collect();
+        let edges = self
+            .edges
+            .into_iter()
+            .filter_map(|edge| {
+                let source = *reached.get(&edge.source)?;
+                let target = *reached.get(&edge.target)?;
+                Some(GraphEdge {
+                    source,
+                    target,
+                    ..edge
+                })
+            })
+            .collect();
+        Ok(Self { keys, edges })
+    }
+
+    /// Writes the graph in Graphviz DOT format
+    pub fn write_dot<W: Write>(&self, mut writer: W) -> Result {
+        writeln!(writer, "graph denylist {{")?;
+        for key in &self.keys {
+            writeln!(writer, "  \"{key}\";")?;
+        }
+        for edge in &self.edges {
+            writeln!(
+                writer,
+                "  \"{}\" -- \"{}\" [reason=\"{}\", carryover={}, provenance=\"{}\"];",
+                self.keys[edge.source],
+                self.keys[edge.target],
+                escape_dot(&edge.reason),
+                edge.carryover,
+                escape_dot(&edge.provenance),
+            )?;
+        }
+        writeln!(writer, "}}")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the graph in GraphML format
+    pub fn write_graphml<W: Write>(&self, mut writer: W) -> Result {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="reason" for="edge" attr.name="reason" attr.type="string"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="carryover" for="edge" attr.name="carryover" attr.type="int"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="provenance" for="edge" attr.name="provenance" attr.type="string"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <graph id="denylist" edgedefault="undirected">"#
+        )?;
+        for key in &self.keys {
+            writeln!(writer, r#"    <node id="{key}"/>"#)?;
+        }
+        for edge in &self.edges {
+            writeln!(
+                writer,
+                r#"    <edge source="{}" target="{}">"#,
+                self.keys[edge.source], self.keys[edge.target]
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="reason">{}</data>"#,
+                escape_xml(&edge.reason)
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="carryover">{}</data>"#,
+                edge.carryover
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="provenance">{}</data>"#,
+                escape_xml(&edge.provenance)
+            )?;
+            writeln!(writer, "    </edge>")?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the graph as a list of edges, one `source target` pair of keys
+    /// per line
+    pub fn write_edgelist<W: Write>(&self, mut writer: W) -> Result {
+        for edge in &self.edges {
+            writeln!(
+                writer,
+                "{} {}",
+                self.keys[edge.source], self.keys[edge.target]
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
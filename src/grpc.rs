@@ -0,0 +1,266 @@
+use crate::{Error, Filter, FilterWatcher, Result};
+use helium_crypto::{PublicKey, PublicKeyBinary};
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::{transport::Channel, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("denylist.service");
+}
+
+use proto::{
+    filter_service_client::FilterServiceClient, filter_service_server::FilterServiceServer,
+    FilterUpdate, LatestFilterReq, LatestFilterResp, MembershipReq, MembershipResp,
+};
+
+/// The largest message the service and client encode or decode. Filters with
+/// about a million entries are larger than the 4 MiB tonic allows by default.
+pub const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+type FilterUpdateStream =
+    Pin<Box<dyn Stream<Item = std::result::Result<FilterUpdate, Status>> + Send>>;
+
+/// Serves the filters of a [`FilterWatcher`] over gRPC.
+///
+/// Only filters the watcher accepted are served, so clients get filters that
+/// verify against the key the watcher is pinned to.
+pub struct FilterService {
+    inner: Arc<ServiceState>,
+    interval: Duration,
+}
+
+struct ServiceState {
+    watcher: Arc<FilterWatcher>,
+    /// The encoded current filter, keyed by serial
+    encoded: Mutex<Option<(u32, Arc<Vec<u8>>)>>,
+}
+
+impl FilterService {
+    pub fn new(watcher: Arc<FilterWatcher>) -> Self {
+        Self {
+            inner: Arc::new(ServiceState {
+                watcher,
+                encoded: Mutex::new(None),
+            }),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// How often filter update streams check for a new filter. Defaults to 1
+    /// second.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn into_server(self) -> FilterServiceServer<Self> {
+        FilterServiceServer::new(self)
+            .max_decoding_message_size(MAX_MESSAGE_SIZE)
+            .max_encoding_message_size(MAX_MESSAGE_SIZE)
+    }
+}
+
+impl ServiceState {
+    /// The current filter as an update if it is newer than the given serial
+    fn update_after(&self, serial: u32) -> std::result::Result<Option<FilterUpdate>, Status> {
+        let filter = self.watcher.current();
+        if filter.serial <= serial {
+            return Ok(None);
+        }
+        let mut encoded = self.encoded.lock().unwrap_or_else(|err| err.into_inner());
+        let bytes = match encoded.as_ref() {
+            Some((cached, bytes)) if *cached == filter.serial => bytes.clone(),
+            _ => {
+                let bytes = Arc::new(
+                    filter
+                        .to_bytes()
+                        .map_err(|err| Status::internal(err.to_string()))?,
+                );
+                *encoded = Some((filter.serial, bytes.clone()));
+                bytes
+            }
+        };
+        Ok(Some(FilterUpdate {
+            serial: filter.serial,
+            filter: bytes.to_vec(),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl proto::filter_service_server::FilterService for FilterService {
+    async fn get_latest_filter(
+        &self,
+        request: Request<LatestFilterReq>,
+    ) -> std::result::Result<Response<LatestFilterResp>, Status> {
+        let filter = self.inner.update_after(request.into_inner().serial)?;
+        Ok(Response::new(LatestFilterResp { filter }))
+    }
+
+    type StreamFilterUpdatesStream = FilterUpdateStream;
+
+    async fn stream_filter_updates(
+        &self,
+        request: Request<LatestFilterReq>,
+    ) -> std::result::Result<Response<Self::StreamFilterUpdatesStream>, Status> {
+        let mut serial = request.into_inner().serial;
+        let state = self.inner.clone();
+        let mut interval = tokio::time::interval(self.interval);
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => (),
+                    _ = tx.closed() => break,
+                }
+                let update = match state.update_after(serial) {
+                    Ok(Some(update)) => update,
+                    Ok(None) => continue,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                serial = update.serial;
+                if tx.send(Ok(update)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn check_membership(
+        &self,
+        request: Request<MembershipReq>,
+    ) -> std::result::Result<Response<MembershipResp>, Status> {
+        let filter = self.inner.watcher.current();
+        let in_filter = request
+            .into_inner()
+            .queries
+            .iter()
+            .map(|query| {
+                if query.key.is_empty() {
+                    return Err(Status::invalid_argument("missing public key"));
+                }
+                let key = PublicKeyBinary::from(query.key.as_slice());
+                Ok(if query.target.is_empty() {
+                    filter.contains(&key)
+                } else {
+                    filter.contains_edge(&key, &PublicKeyBinary::from(query.target.as_slice()))
+                })
+            })
+            .collect::<std::result::Result<Vec<bool>, Status>>()?;
+        Ok(Response::new(MembershipResp {
+            serial: filter.serial,
+            in_filter,
+        }))
+    }
+}
+
+/// A client for a [`FilterService`] that verifies received filters against a
+/// pinned public key.
+pub struct FilterClient {
+    client: FilterServiceClient<Channel>,
+    public_key: PublicKey,
+}
+
+impl FilterClient {
+    /// Connects to the filter service at the given endpoint, for example
+    /// `http://127.0.0.1:50051`.
+    pub async fn connect(endpoint: String, public_key: PublicKey) -> Result<Self> {
+        let client = FilterServiceClient::connect(endpoint)
+            .await
+            .map_err(|err| Error::grpc(&err.to_string()))?
+            .max_decoding_message_size(MAX_MESSAGE_SIZE)
+            .max_encoding_message_size(MAX_MESSAGE_SIZE);
+        Ok(Self { client, public_key })
+    }
+
+    /// Returns the latest filter if it is newer than the given serial.
+    pub async fn latest_filter(&mut self, serial: u32) -> Result<Option<Filter>> {
+        let resp = self
+            .client
+            .get_latest_filter(LatestFilterReq { serial })
+            .await
+            .map_err(|status| Error::grpc(status.message()))?
+            .into_inner();
+        resp.filter
+            .map(|update| self.verify_update(&update, serial))
+            .transpose()
+    }
+
+    /// Returns a stream of filters starting at the latest filter newer than
+    /// the given serial. Every filter in the stream is verified and newer than
+    /// the one before it.
+    pub async fn filter_updates(
+        &mut self,
+        serial: u32,
+    ) -> Result<impl Stream<Item = Result<Filter>>> {
+        let updates = self
+            .client
+            .stream_filter_updates(LatestFilterReq { serial })
+            .await
+            .map_err(|status| Error::grpc(status.message()))?
+            .into_inner();
+        let public_key = self.public_key.clone();
+        let mut serial = serial;
+        Ok(updates.map(move |update| {
+            let update = update.map_err(|status| Error::grpc(status.message()))?;
+            let filter = verify_update(&public_key, &update, serial)?;
+            serial = filter.serial;
+            Ok(filter)
+        }))
+    }
+
+    /// Checks the given public keys, or edges if a target is given, against
+    /// the latest filter of the service. Returns the serial of that filter
+    /// and whether each query is in the filter.
+    pub async fn check_membership(
+        &mut self,
+        queries: &[(PublicKeyBinary, Option<PublicKeyBinary>)],
+    ) -> Result<MembershipResp> {
+        let queries = queries
+            .iter()
+            .map(|(key, target)| proto::Query {
+                key: key.as_ref().to_vec(),
+                target: target
+                    .as_ref()
+                    .map(|target| target.as_ref().to_vec())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        let resp = self
+            .client
+            .check_membership(MembershipReq { queries })
+            .await
+            .map_err(|status| Error::grpc(status.message()))?;
+        Ok(resp.into_inner())
+    }
+
+    fn verify_update(&self, update: &FilterUpdate, serial: u32) -> Result<Filter> {
+        verify_update(&self.public_key, update, serial)
+    }
+}
+
+/// Decodes and verifies a filter update, and checks that it is newer than the
+/// given serial.
+fn verify_update(public_key: &PublicKey, update: &FilterUpdate, serial: u32) -> Result<Filter> {
+    let filter = Filter::from_bytes(&update.filter)?;
+    filter.verify(public_key)?;
+    if filter.serial != update.serial {
+        return Err(Error::grpc("Filter serial does not match update serial"));
+    }
+    if filter.serial <= serial {
+        return Err(Error::grpc(&format!(
+            "Filter serial {} is not newer than serial {serial}",
+            filter.serial
+        )));
+    }
+    Ok(filter)
+}
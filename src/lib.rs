@@ -1,5 +1,9 @@
+#![cfg_attr(not(feature = "generator"), no_std)]
+
+#[cfg(feature = "generator")]
 pub type Result<T = ()> = std::result::Result<T, Error>;
 
+#[cfg(feature = "generator")]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("io: {0}")]
@@ -18,27 +22,134 @@ pub enum Error {
     Crypto(#[from] helium_crypto::Error),
     #[error("filter: {0}")]
     Filter(String),
+    #[error("bundle: {0}")]
+    Bundle(String),
+    #[error("key rotation: {0}")]
+    Rotation(String),
+    #[error("policy: {0}")]
+    Policy(String),
+    #[error("pkcs11: {0}")]
+    Pkcs11(String),
+    #[error("grpc: {0}")]
+    Grpc(String),
+    #[error("descriptor: {0}")]
+    Descriptor(String),
+    #[error("verify: {0}")]
+    Verify(#[from] verify::Error),
 }
 
+#[cfg(feature = "generator")]
 impl Error {
     pub fn filter(err: &str) -> Self {
         Self::Filter(err.to_string())
     }
+
+    pub fn bundle(err: &str) -> Self {
+        Self::Bundle(err.to_string())
+    }
+
+    pub fn rotation(err: &str) -> Self {
+        Self::Rotation(err.to_string())
+    }
+
+    pub fn policy(err: &str) -> Self {
+        Self::Policy(err.to_string())
+    }
+
+    pub fn pkcs11(err: &str) -> Self {
+        Self::Pkcs11(err.to_string())
+    }
+
+    pub fn grpc(err: &str) -> Self {
+        Self::Grpc(err.to_string())
+    }
+
+    pub fn descriptor(err: &str) -> Self {
+        Self::Descriptor(err.to_string())
+    }
 }
 
+pub mod verify;
+pub use verify::{FILTER_ENVELOPE_CONTEXT, FILTER_ENVELOPE_VERSION, FILTTER_VERSION};
+
+#[cfg(feature = "generator")]
 mod filter;
-pub use filter::{edge_hash, edge_order, public_key_hash, Filter, FILTTER_VERSION};
+#[cfg(feature = "generator")]
+pub use filter::{edge_hash, edge_order, public_key_hash, Filter, FilterData, FilterKind};
+
+#[cfg(feature = "generator")]
+mod view;
+#[cfg(feature = "generator")]
+pub use view::FilterView;
 
+#[cfg(feature = "generator")]
+mod watcher;
+#[cfg(feature = "generator")]
+pub use watcher::{FilterWatcher, FilterWatcherBuilder};
+
+#[cfg(feature = "generator")]
 mod manifest;
+#[cfg(feature = "generator")]
 pub use manifest::{
-    Manifest, ManifestAddres, ManifestSignature, ManifestSignatureVerify, PublicKeyManifest,
+    Attestation, CollectStatus, DetachedSignature, Manifest, ManifestAddres, ManifestSignature,
+    ManifestSignatureVerify, PublicKeyManifest, ATTESTATION_CONTEXT,
 };
 
+#[cfg(feature = "generator")]
+mod policy;
+#[cfg(feature = "generator")]
+pub use policy::{ClauseVerify, PolicyClause, PolicyMember, PolicyVerify};
+
+#[cfg(feature = "generator")]
+mod rotation;
+#[cfg(feature = "generator")]
+pub use rotation::{verify_chain, KeyRotation, KEY_ROTATION_CONTEXT};
+
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+#[cfg(feature = "pkcs11")]
+pub use pkcs11::Pkcs11Signer;
+
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "grpc")]
+pub use grpc::{FilterClient, FilterService};
+
+#[cfg(feature = "generator")]
 mod descriptor;
-pub use descriptor::{Descriptor, Edges};
+#[cfg(feature = "generator")]
+pub use descriptor::{
+    Descriptor, DescriptorBuilder, DescriptorCompression, Edges, Header, MergeReport, Promotion,
+    PromotionRule, Reason, ReasonCount, RemovalSummary, SourceConflict, SourceEntry, SourceSummary,
+    DESCRIPTOR_MAJOR_VERSION, DESCRIPTOR_MINOR_VERSION,
+};
+
+#[cfg(feature = "generator")]
+mod graph;
+#[cfg(feature = "generator")]
+pub use graph::DescriptorGraph;
+
+#[cfg(feature = "generator")]
+mod stats;
+#[cfg(feature = "generator")]
+pub use stats::{DescriptorStats, KeyDegree};
+
+#[cfg(feature = "generator")]
+mod reason;
+#[cfg(feature = "generator")]
+pub use reason::{ReasonCode, ReasonCodes};
+
+#[cfg(feature = "generator")]
+mod bundle;
+#[cfg(feature = "generator")]
+pub use bundle::{
+    Artifact, Bundle, BundleCheck, BundleEntry, BundleIndex, BundleVerify, BUNDLE_INDEX,
+};
 
+#[cfg(feature = "generator")]
 pub use xorf;
 
+#[cfg(feature = "generator")]
 pub mod base64_serde {
     use base64::{engine::general_purpose::STANDARD, Engine};
     use serde::{de, Deserialize, Deserializer, Serializer};
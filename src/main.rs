@@ -1,35 +1,86 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use helium_crypto::Network;
+use std::{path::PathBuf, process::ExitCode};
 mod cmd;
 
 #[derive(Debug, Parser)]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(name = env!("CARGO_BIN_NAME"))]
 pub struct Cli {
+    /// The format to report errors in
+    #[arg(long, value_enum, default_value_t)]
+    output: cmd::OutputFormat,
+
+    /// The configuration file with default paths and options. Defaults to
+    /// xorf-generator.toml in the working directory if it exists
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// The profile of the configuration file to use
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// The network the keys in public key files have to be on
+    #[arg(long)]
+    network: Option<Network>,
+
     #[command(subcommand)]
     cmd: Cmd,
 }
 
 #[derive(Debug, clap::Subcommand)]
 pub enum Cmd {
+    Bundle(cmd::bundle::Cmd),
+    Config(cmd::config::Cmd),
     Descriptor(cmd::descriptor::Cmd),
     Data(cmd::data::Cmd),
     Filter(cmd::filter::Cmd),
     Key(cmd::key::Cmd),
     Manifest(cmd::manifest::Cmd),
+    Pipeline(cmd::pipeline::Cmd),
+    Serve(cmd::serve::Cmd),
 }
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
-    run(cli)
+fn main() -> ExitCode {
+    // The configuration sets the defaults of the arguments, so it is loaded
+    // before they are parsed
+    let args = std::env::args_os().collect::<Vec<_>>();
+    let config = match cmd::config::Config::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            let output = cmd::config::option_value(&args, "--output")
+                .and_then(|value| cmd::OutputFormat::from_str(value.to_str()?, false).ok())
+                .unwrap_or_default();
+            return cmd::error::report(&err, output);
+        }
+    };
+    let command = match &config {
+        Some(config) => config.apply(Cli::command()),
+        None => Cli::command(),
+    };
+    let cli =
+        Cli::from_arg_matches(&command.get_matches_from(args)).unwrap_or_else(|err| err.exit());
+    let output = cli.output;
+    match run(cli, config.as_ref()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => cmd::error::report(&err, output),
+    }
 }
 
-fn run(cli: Cli) -> Result<()> {
+fn run(cli: Cli, config: Option<&cmd::config::Config>) -> Result<()> {
+    if let Some(network) = cli.network {
+        cmd::require_network(network);
+    }
     match cli.cmd {
+        Cmd::Bundle(cmd) => cmd.run(),
+        Cmd::Config(cmd) => cmd.run(config),
         Cmd::Data(cmd) => cmd.run(),
         Cmd::Descriptor(cmd) => cmd.run(),
         Cmd::Filter(cmd) => cmd.run(),
         Cmd::Key(cmd) => cmd.run(),
         Cmd::Manifest(cmd) => cmd.run(),
+        Cmd::Pipeline(cmd) => cmd.run(),
+        Cmd::Serve(cmd) => cmd.run(),
     }
 }
@@ -1,22 +1,82 @@
-use crate::{base64_serde, Result};
+use crate::{
+    base64_serde, ClauseVerify, Error, Filter, PolicyClause, PolicyVerify, Result, FILTTER_VERSION,
+};
+use bytes::{BufMut, BytesMut};
 use helium_crypto::{multihash, multisig, Network, PublicKey, Verify};
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::BufReader, ops::Deref, path::Path};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    ops::Deref,
+    path::Path,
+};
+
+/// Domain separation prefix for the bytes signed by an attestation
+pub const ATTESTATION_CONTEXT: &[u8] = b"xorf-generator/attestation/v1";
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Manifest {
+    /// The filter version the signatures are for. This determines whether
+    /// members sign the full signing data or the signing envelope
+    #[serde(
+        default = "Manifest::default_version",
+        skip_serializing_if = "Manifest::is_default_version"
+    )]
+    pub version: u8,
     pub serial: u32,
     pub hash: String,
     pub signatures: Vec<ManifestSignature>,
 }
 
 impl Manifest {
+    fn default_version() -> u8 {
+        FILTTER_VERSION
+    }
+
+    fn is_default_version(version: &u8) -> bool {
+        *version == FILTTER_VERSION
+    }
+
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = File::open(path)?;
-        let manifest = serde_json::from_reader(BufReader::new(file))?;
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let manifest = serde_json::from_reader(BufReader::new(reader))?;
         Ok(manifest)
     }
 
+    /// Creates an unsigned manifest for the given filter with an empty
+    /// signature entry for every member of the given key manifest.
+    pub fn for_filter(filter: &Filter, key_manifest: &PublicKeyManifest) -> Result<Self> {
+        Ok(Self {
+            version: filter.version,
+            serial: filter.serial,
+            hash: base64_serde::encode(&filter.hash()?),
+            signatures: key_manifest
+                .public_keys
+                .iter()
+                .map(ManifestSignature::from)
+                .collect(),
+        })
+    }
+
+    /// Returns the verification status of each signature in the manifest
+    /// against the given signing bytes.
+    pub fn verify_signatures(&self, msg: &[u8]) -> Vec<ManifestSignatureVerify> {
+        self.signatures
+            .iter()
+            .map(|signature| signature.verify(msg))
+            .collect()
+    }
+
+    /// Returns the distinct members of the given key manifest whose signature
+    /// in this manifest verifies the given signing bytes.
+    pub fn signers(&self, key_manifest: &PublicKeyManifest, msg: &[u8]) -> Vec<PublicKey> {
+        verified_signers(&self.signatures, &key_manifest.public_keys(), msg)
+    }
+
     pub fn sign(&self, key_manifest: &PublicKeyManifest) -> Result<Vec<u8>> {
         let public_key = key_manifest.public_key()?;
         let keys = key_manifest.public_keys();
@@ -30,21 +90,199 @@ impl Manifest {
         let signature = multisig::Signature::new(&public_key, &keys, &signatures)?;
         Ok(signature.to_vec())
     }
+
+    /// Validates a detached signature against this manifest and the given
+    /// signing bytes and, if it is valid, merges it into the manifest
+    /// signatures.
+    pub fn collect(
+        &mut self,
+        detached: &DetachedSignature,
+        key_manifest: &PublicKeyManifest,
+        msg: &[u8],
+    ) -> CollectStatus {
+        if detached.serial != self.serial {
+            return CollectStatus::Stale;
+        }
+        if detached.hash != self.hash {
+            return CollectStatus::Invalid("hash does not match manifest hash".to_string());
+        }
+        if !key_manifest.public_keys().contains(&detached.address) {
+            return CollectStatus::Invalid("address is not a public key".to_string());
+        }
+        let signature = ManifestSignature {
+            address: detached.address.clone(),
+            signature: detached.signature.clone(),
+            attestation: detached.attestation.clone(),
+        };
+        let verify = signature.verify(msg);
+        if !verify.verified {
+            return CollectStatus::Invalid("signature does not verify".to_string());
+        }
+        if verify.attestation_verified == Some(false) {
+            return CollectStatus::Invalid("attestation does not verify".to_string());
+        }
+        if self.signatures.iter().any(|existing| {
+            existing.address() == signature.address() && existing.verify(msg).is_verified()
+        }) {
+            return CollectStatus::Duplicate;
+        }
+        match self
+            .signatures
+            .iter_mut()
+            .find(|existing| existing.address() == signature.address())
+        {
+            Some(existing) => *existing = signature,
+            None => self.signatures.push(signature),
+        }
+        CollectStatus::Merged
+    }
+}
+
+/// A signature by a single member, kept in its own file so members do not
+/// have to edit a shared manifest.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DetachedSignature {
+    pub address: ManifestAddres,
+    pub hash: String,
+    pub serial: u32,
+    #[serde(with = "base64_serde")]
+    pub signature: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Attestation>,
+}
+
+impl DetachedSignature {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        let signature = serde_json::from_reader(BufReader::new(file))?;
+        Ok(signature)
+    }
+
+    /// The conventional file name for a detached signature by the given
+    /// address
+    pub fn file_name(address: &PublicKey) -> String {
+        format!("sig-{address}.json")
+    }
+}
+
+/// The outcome of collecting a detached signature into a manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "reason")]
+pub enum CollectStatus {
+    /// The signature was added to the manifest
+    Merged,
+    /// The manifest already has a valid signature for the address
+    Duplicate,
+    /// The signature is for a different serial than the manifest
+    Stale,
+    /// The signature does not verify for the manifest
+    Invalid(String),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Returns the distinct members whose signature in the given list verifies
+/// the given signing bytes.
+pub(crate) fn verified_signers(
+    signatures: &[ManifestSignature],
+    members: &[PublicKey],
+    msg: &[u8],
+) -> Vec<PublicKey> {
+    let mut signers: Vec<PublicKey> = vec![];
+    for signature in signatures {
+        let address = signature.address();
+        if members.contains(address)
+            && !signers.contains(address)
+            && signature.verify(msg).is_verified()
+        {
+            signers.push(address.clone());
+        }
+    }
+    signers
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PublicKeyManifest {
     pub public_keys: Vec<ManifestAddres>,
     pub required: u8,
+    /// Optional signing policy clauses which must all be satisfied in
+    /// addition to the required number of signatures
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policy: Vec<PolicyClause>,
 }
 
 impl PublicKeyManifest {
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = File::open(path)?;
-        let manifest = serde_json::from_reader(BufReader::new(file))?;
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let manifest: Self = serde_json::from_reader(BufReader::new(reader))?;
+        manifest.validate_policy()?;
         Ok(manifest)
     }
 
+    /// Checks that every policy clause only refers to keys of this manifest,
+    /// lists every member once and can be satisfied.
+    pub fn validate_policy(&self) -> Result<()> {
+        let public_keys = self.public_keys();
+        for clause in &self.policy {
+            if let Some(member) = clause
+                .members
+                .iter()
+                .find(|member| !public_keys.contains(member.address()))
+            {
+                return Err(Error::policy(&format!(
+                    "policy clause {} member {} is not a public key",
+                    clause.name,
+                    member.address()
+                )));
+            }
+            if let Some(member) = clause
+                .members
+                .iter()
+                .enumerate()
+                .find_map(|(index, member)| {
+                    clause.members[..index]
+                        .iter()
+                        .any(|other| other.address() == member.address())
+                        .then_some(member)
+                })
+            {
+                return Err(Error::policy(&format!(
+                    "policy clause {} member {} is listed more than once",
+                    clause.name,
+                    member.address()
+                )));
+            }
+            if clause.threshold == 0 || clause.threshold > clause.total_weight()? {
+                return Err(Error::policy(&format!(
+                    "policy clause {} threshold {} can not be met",
+                    clause.name, clause.threshold
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates the required number of signatures and every policy clause
+    /// against the given signers.
+    pub fn evaluate_policy(&self, signers: &[PublicKey]) -> PolicyVerify {
+        let public_keys = self.public_keys();
+        let signed: Vec<&PublicKey> = signers
+            .iter()
+            .filter(|signer| public_keys.contains(signer))
+            .collect();
+        let required = ClauseVerify {
+            name: "required".to_string(),
+            threshold: self.required as u32,
+            weight: signed.len() as u32,
+            satisfied: signed.len() >= self.required as usize,
+            signers: signed.iter().map(|signer| signer.to_string()).collect(),
+        };
+        std::iter::once(required)
+            .chain(self.policy.iter().map(|clause| clause.evaluate(signers)))
+            .collect()
+    }
+
     pub fn public_key(&self) -> Result<PublicKey> {
         let public_keys: Vec<PublicKey> =
             self.public_keys.iter().map(|k| k.deref().clone()).collect();
@@ -67,17 +305,92 @@ pub struct ManifestSignature {
     address: ManifestAddres,
     #[serde(with = "base64_serde")]
     signature: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attestation: Option<Attestation>,
 }
 
 impl ManifestSignature {
+    pub fn address(&self) -> &PublicKey {
+        &self.address
+    }
+
+    pub fn attestation(&self) -> Option<&Attestation> {
+        self.attestation.as_ref()
+    }
+
+    /// Whether the member has added a signature
+    pub fn is_signed(&self) -> bool {
+        !self.signature.is_empty()
+    }
+
+    /// Verifies the signature, and the attestation if there is one, against
+    /// the given signing bytes.
     pub fn verify(&self, msg: &[u8]) -> ManifestSignatureVerify {
         ManifestSignatureVerify {
             signature: self.clone(),
             verified: self.address.verify(msg, &self.signature).is_ok(),
+            attestation_verified: self
+                .attestation
+                .as_ref()
+                .map(|attestation| attestation.verify(&self.address, &Sha256::digest(msg))),
         }
     }
 }
 
+/// Statements a signer makes about what they reviewed before signing. The
+/// attestation is signed separately by the signer over a canonical encoding
+/// of its fields and the hash of the signing data.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Attestation {
+    /// Unix time in seconds at which the attestation was made
+    pub timestamp: u64,
+    /// Base64 encoded sha256 hash of the descriptor that was reviewed
+    pub descriptor_hash: String,
+    /// Base64 encoded sha256 hash of the reviewed diff against the previous
+    /// descriptor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, with = "base64_serde")]
+    pub signature: Vec<u8>,
+}
+
+impl Attestation {
+    /// Returns the bytes a signer signs for this attestation, given the hash
+    /// of the signing data the attestation is for.
+    ///
+    /// The encoding is the attestation context followed by the data hash,
+    /// the timestamp as a little endian u64, and the decoded descriptor hash,
+    /// diff hash and comment, each prefixed with its length as a little endian
+    /// u32. A missing diff hash or comment is encoded as empty.
+    pub fn to_signing_bytes(&self, data_hash: &[u8]) -> Result<Vec<u8>> {
+        let diff_hash = match &self.diff_hash {
+            Some(hash) => base64_serde::decode(hash)?,
+            None => vec![],
+        };
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(ATTESTATION_CONTEXT);
+        put_field(&mut buf, data_hash);
+        buf.put_u64_le(self.timestamp);
+        put_field(&mut buf, &base64_serde::decode(&self.descriptor_hash)?);
+        put_field(&mut buf, &diff_hash);
+        put_field(&mut buf, self.comment.as_deref().unwrap_or("").as_bytes());
+        Ok(buf.to_vec())
+    }
+
+    pub fn verify(&self, address: &PublicKey, data_hash: &[u8]) -> bool {
+        self.to_signing_bytes(data_hash)
+            .map(|msg| address.verify(&msg, &self.signature).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+pub(crate) fn put_field(buf: &mut BytesMut, data: &[u8]) {
+    buf.put_u32_le(data.len() as u32);
+    buf.extend_from_slice(data);
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ManifestAddres(#[serde(with = "public_key")] PublicKey);
 
@@ -88,6 +401,12 @@ impl Deref for ManifestAddres {
     }
 }
 
+impl From<PublicKey> for ManifestAddres {
+    fn from(val: PublicKey) -> Self {
+        Self(val)
+    }
+}
+
 impl From<ManifestAddres> for PublicKey {
     fn from(val: ManifestAddres) -> Self {
         val.0
@@ -99,6 +418,7 @@ impl From<&ManifestAddres> for ManifestSignature {
         Self {
             address: val.clone(),
             signature: vec![],
+            attestation: None,
         }
     }
 }
@@ -108,6 +428,21 @@ pub struct ManifestSignatureVerify {
     #[serde(flatten)]
     signature: ManifestSignature,
     verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attestation_verified: Option<bool>,
+}
+
+impl ManifestSignatureVerify {
+    pub fn signature(&self) -> &ManifestSignature {
+        &self.signature
+    }
+
+    /// Whether the signature verifies and its attestation, if there is one,
+    /// verifies as well. Only such signatures count towards the required
+    /// signatures and the signing policy.
+    pub fn is_verified(&self) -> bool {
+        self.verified && self.attestation_verified != Some(false)
+    }
 }
 
 mod public_key {
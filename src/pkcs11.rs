@@ -0,0 +1,208 @@
+//! Signing with keys held in a PKCS#11 token, such as an HSM or SoftHSM.
+//!
+//! ECC P-256 keys sign with `CKM_ECDSA` over the sha256 digest of the message
+//! and Ed25519 keys with `CKM_EDDSA` over the message itself, which produces
+//! the same signatures helium-crypto keypairs do.
+use crate::{Error, Result};
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    error::RvError,
+    mechanism::{
+        eddsa::{EddsaParams, EddsaSignatureScheme},
+        Mechanism,
+    },
+    object::{Attribute, AttributeType, KeyType as CkKeyType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    types::AuthPin,
+};
+use helium_crypto::{ecc_compact, KeyTag, KeyType, Network, PublicKey, Verify};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// DER encoded object identifier of the P-256 (prime256v1) curve
+const P256_PARAMS: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+
+fn pkcs11_error(err: cryptoki::error::Error) -> Error {
+    Error::pkcs11(&err.to_string())
+}
+
+/// Loads and initializes the given PKCS#11 module and opens a logged in
+/// session with the token with the given label.
+fn open_session(module: &Path, token: &str, pin: &str) -> Result<Session> {
+    let pkcs11 =
+        Pkcs11::new(module).map_err(|err| Error::pkcs11(&format!("loading module: {err}")))?;
+    match pkcs11.initialize(CInitializeArgs::OsThreads) {
+        Err(cryptoki::error::Error::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => (),
+        result => result.map_err(pkcs11_error)?,
+    }
+    let mut slot = None;
+    for candidate in pkcs11.get_slots_with_token().map_err(pkcs11_error)? {
+        let info = pkcs11.get_token_info(candidate).map_err(pkcs11_error)?;
+        if info.label().trim_end() == token {
+            slot = Some(candidate);
+            break;
+        }
+    }
+    let slot = slot.ok_or_else(|| Error::pkcs11(&format!("no token with label {token}")))?;
+    let session = pkcs11.open_ro_session(slot).map_err(pkcs11_error)?;
+    match session.login(UserType::User, Some(&AuthPin::new(pin.into()))) {
+        Err(cryptoki::error::Error::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => (),
+        result => result.map_err(pkcs11_error)?,
+    }
+    Ok(session)
+}
+
+fn find_object(session: &Session, class: ObjectClass, label: &str) -> Result<Option<ObjectHandle>> {
+    let template = [
+        Attribute::Class(class),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ];
+    let objects = session.find_objects(&template).map_err(pkcs11_error)?;
+    Ok(objects.first().copied())
+}
+
+/// Converts the EC point of the given public key object to a helium public key
+/// on the given network. P-256 keys must be ECC compact to be usable.
+fn ec_public_key(session: &Session, object: ObjectHandle, network: Network) -> Result<PublicKey> {
+    let attributes = session
+        .get_attributes(
+            object,
+            &[
+                AttributeType::KeyType,
+                AttributeType::EcParams,
+                AttributeType::EcPoint,
+            ],
+        )
+        .map_err(pkcs11_error)?;
+    let (mut key_type, mut params, mut point) = (None, None, None);
+    for attribute in attributes {
+        match attribute {
+            Attribute::KeyType(value) => key_type = Some(value),
+            Attribute::EcParams(value) => params = Some(value),
+            Attribute::EcPoint(value) => point = Some(value),
+            _ => (),
+        }
+    }
+    let key_type = key_type.ok_or_else(|| Error::pkcs11("public key has no key type"))?;
+    let point = point.ok_or_else(|| Error::pkcs11("public key has no ec point"))?;
+    let tag = |key_type| u8::from(KeyTag { network, key_type });
+    if key_type == CkKeyType::EC {
+        if params.as_deref() != Some(P256_PARAMS) {
+            return Err(Error::pkcs11("ec key is not a P-256 key"));
+        }
+        let point = octet_string(&point, 65);
+        // fails for keys that are not ecc compact
+        ecc_compact::PublicKey::try_from(point)?;
+        // ecc compact key tag followed by the x coordinate
+        let mut bytes = vec![tag(KeyType::EccCompact)];
+        bytes.extend_from_slice(point.get(1..33).unwrap_or_default());
+        Ok(PublicKey::from_bytes(bytes)?)
+    } else if key_type == CkKeyType::EC_EDWARDS {
+        let mut bytes = vec![tag(KeyType::Ed25519)];
+        bytes.extend_from_slice(octet_string(&point, 32));
+        Ok(PublicKey::from_bytes(bytes)?)
+    } else {
+        Err(Error::pkcs11(&format!("unsupported key type {key_type}")))
+    }
+}
+
+/// A signing key held in a PKCS#11 token.
+///
+/// The token session stays open, and logged in, until the signer is dropped.
+pub struct Pkcs11Signer {
+    session: Session,
+    key: ObjectHandle,
+    public_key: PublicKey,
+}
+
+impl Pkcs11Signer {
+    /// Loads the given PKCS#11 module, logs in to the token with the given
+    /// label and looks up the private and public key with the given label.
+    /// The public key of the signer is on the given network.
+    pub fn open<P: AsRef<Path>>(
+        module: P,
+        token: &str,
+        pin: &str,
+        label: &str,
+        network: Network,
+    ) -> Result<Self> {
+        let session = open_session(module.as_ref(), token, pin)?;
+        let key = find_object(&session, ObjectClass::PRIVATE_KEY, label)?
+            .ok_or_else(|| Error::pkcs11(&format!("no private key with label {label}")))?;
+        let public_key = find_object(&session, ObjectClass::PUBLIC_KEY, label)?
+            .ok_or_else(|| Error::pkcs11(&format!("no public key with label {label}")))?;
+        let public_key = ec_public_key(&session, public_key, network)?;
+        Ok(Self {
+            session,
+            key,
+            public_key,
+        })
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Signs the given message with the token key and checks the resulting
+    /// signature against the public key of the token key.
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        let signature = match self.public_key.key_type() {
+            KeyType::EccCompact => {
+                let digest = Sha256::digest(msg);
+                let raw = self
+                    .session
+                    .sign(&Mechanism::Ecdsa, self.key, &digest)
+                    .map_err(pkcs11_error)?;
+                der_signature(&raw)?
+            }
+            _ => {
+                let mechanism = Mechanism::Eddsa(EddsaParams::new(EddsaSignatureScheme::Pure));
+                self.session
+                    .sign(&mechanism, self.key, msg)
+                    .map_err(pkcs11_error)?
+            }
+        };
+        self.public_key.verify(msg, &signature)?;
+        Ok(signature)
+    }
+}
+
+/// Returns the contents of a DER encoded octet string, or the given value if
+/// it already has the expected raw length. Tokens differ in whether they wrap
+/// `CKA_EC_POINT`.
+fn octet_string(value: &[u8], raw_len: usize) -> &[u8] {
+    if value.len() != raw_len && value.len() > 2 && value[0] == 0x04 {
+        &value[2..]
+    } else {
+        value
+    }
+}
+
+/// Converts a raw `r || s` ECDSA signature as returned by `CKM_ECDSA` to the
+/// DER encoding helium-crypto uses.
+fn der_signature(raw: &[u8]) -> Result<Vec<u8>> {
+    if raw.len() != 64 {
+        return Err(Error::pkcs11("invalid ecdsa signature length"));
+    }
+    let integer = |bytes: &[u8]| {
+        let start = bytes
+            .iter()
+            .position(|b| *b != 0)
+            .unwrap_or(bytes.len() - 1);
+        let bytes = &bytes[start..];
+        let mut der = vec![0x02];
+        if bytes[0] & 0x80 != 0 {
+            der.push(bytes.len() as u8 + 1);
+            der.push(0);
+        } else {
+            der.push(bytes.len() as u8);
+        }
+        der.extend_from_slice(bytes);
+        der
+    };
+    let (r, s) = (integer(&raw[..32]), integer(&raw[32..]));
+    let mut der = vec![0x30, (r.len() + s.len()) as u8];
+    der.extend(r);
+    der.extend(s);
+    Ok(der)
+}
@@ -0,0 +1,105 @@
+use crate::{Error, ManifestAddres, Result};
+use helium_crypto::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// A clause of a signing policy. The clause is satisfied when the summed
+/// weight of its members that signed reaches the clause threshold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyClause {
+    pub name: String,
+    pub threshold: u32,
+    pub members: Vec<PolicyMember>,
+}
+
+/// A member of a policy clause, given either as just an address with weight
+/// 1, or as an address with an explicit weight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PolicyMember {
+    Key(ManifestAddres),
+    Weighted {
+        address: ManifestAddres,
+        weight: u32,
+    },
+}
+
+impl PolicyMember {
+    pub fn address(&self) -> &PublicKey {
+        match self {
+            Self::Key(address) => address,
+            Self::Weighted { address, .. } => address,
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        match self {
+            Self::Key(_) => 1,
+            Self::Weighted { weight, .. } => *weight,
+        }
+    }
+}
+
+impl PolicyClause {
+    pub fn total_weight(&self) -> Result<u32> {
+        self.members.iter().try_fold(0u32, |total, member| {
+            total.checked_add(member.weight()).ok_or_else(|| {
+                Error::policy(&format!("policy clause {} weight overflows", self.name))
+            })
+        })
+    }
+
+    /// Evaluates the clause against the given signers. A member that is
+    /// listed more than once only counts once.
+    pub fn evaluate(&self, signers: &[PublicKey]) -> ClauseVerify {
+        let mut signed: Vec<&PolicyMember> = vec![];
+        for member in &self.members {
+            if signers.contains(member.address())
+                && !signed
+                    .iter()
+                    .any(|other| other.address() == member.address())
+            {
+                signed.push(member);
+            }
+        }
+        let weight = signed.iter().fold(0u32, |weight, member| {
+            weight.saturating_add(member.weight())
+        });
+        ClauseVerify {
+            name: self.name.clone(),
+            threshold: self.threshold,
+            weight,
+            satisfied: weight >= self.threshold,
+            signers: signed
+                .iter()
+                .map(|member| member.address().to_string())
+                .collect(),
+        }
+    }
+}
+
+/// The result of evaluating every clause of a signing policy against a set
+/// of signers.
+#[derive(Debug, Serialize)]
+pub struct PolicyVerify {
+    pub satisfied: bool,
+    pub clauses: Vec<ClauseVerify>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClauseVerify {
+    pub name: String,
+    pub threshold: u32,
+    pub weight: u32,
+    pub satisfied: bool,
+    pub signers: Vec<String>,
+}
+
+impl FromIterator<ClauseVerify> for PolicyVerify {
+    fn from_iter<I: IntoIterator<Item = ClauseVerify>>(iter: I) -> Self {
+        let clauses: Vec<ClauseVerify> = iter.into_iter().collect();
+        Self {
+            satisfied: clauses.iter().all(|clause| clause.satisfied),
+            clauses,
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{Error, Reason, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+/// A registry of reason codes for descriptor nodes and edges.
+///
+/// The registry maps every code to a numeric id, which is what descriptors
+/// store, and a description of the code:
+///
+/// ```json
+/// {
+///   "fraud": { "id": 1, "description": "Fraudulent location assertions" },
+///   "gaming": { "id": 2, "description": "Witness gaming" }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ReasonCodes(IndexMap<String, ReasonCode>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReasonCode {
+    pub id: u32,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl ReasonCodes {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let codes: Self = serde_json::from_reader(BufReader::new(reader))?;
+        codes.check()?;
+        Ok(codes)
+    }
+
+    /// Checks that every code has a unique, non zero id. An id of zero is
+    /// reserved for entries without a reason code.
+    pub fn check(&self) -> Result {
+        let mut ids = HashSet::new();
+        for (code, entry) in &self.0 {
+            if entry.id == 0 {
+                return Err(Error::descriptor(&format!(
+                    "Reason code {code} has reserved id 0"
+                )));
+            }
+            if !ids.insert(entry.id) {
+                return Err(Error::descriptor(&format!(
+                    "Reason code {code} reuses id {}",
+                    entry.id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The id of the given code
+    pub fn id(&self, code: &str) -> Option<u32> {
+        self.0.get(code).map(|entry| entry.id)
+    }
+
+    /// The code with the given id
+    pub fn code(&self, id: u32) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(_, entry)| entry.id == id)
+            .map(|(code, _)| code.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The reason code table to store in a descriptor
+    pub fn to_reasons(&self) -> Vec<Reason> {
+        self.0
+            .iter()
+            .map(|(code, entry)| Reason {
+                id: entry.id,
+                code: code.clone(),
+                description: entry.description.clone(),
+            })
+            .collect()
+    }
+
+    /// The reason codes stored in a descriptor
+    pub fn from_reasons(reasons: &[Reason]) -> Self {
+        Self(
+            reasons
+                .iter()
+                .map(|reason| {
+                    (
+                        reason.code.clone(),
+                        ReasonCode {
+                            id: reason.id,
+                            description: reason.description.clone(),
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    manifest::{put_field, verified_signers},
+    Error, ManifestSignature, PublicKeyManifest, Result,
+};
+use bytes::{BufMut, BytesMut};
+use helium_crypto::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+/// Domain separation context for key rotation signing bytes
+pub const KEY_ROTATION_CONTEXT: &[u8] = b"xorf-generator/key-rotation/v1";
+
+/// A statement by the members of an existing multisig key that authorizes a
+/// new multisig key.
+///
+/// A chain of rotations starting at a pinned root key lets consumers follow
+/// key changes without having to be rebuilt with the new key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub from: PublicKeyManifest,
+    pub to: PublicKeyManifest,
+    pub signatures: Vec<ManifestSignature>,
+}
+
+impl KeyRotation {
+    /// Creates an unsigned rotation statement with an empty signature entry
+    /// for every member of the current key.
+    pub fn new(from: PublicKeyManifest, to: PublicKeyManifest) -> Self {
+        let signatures = from
+            .public_keys
+            .iter()
+            .map(ManifestSignature::from)
+            .collect();
+        Self {
+            from,
+            to,
+            signatures,
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let rotation: Self = serde_json::from_reader(BufReader::new(reader))?;
+        rotation.from.validate_policy()?;
+        rotation.to.validate_policy()?;
+        Ok(rotation)
+    }
+
+    /// The bytes each member of the current key signs: the rotation context
+    /// followed by the current multisig public key and signing policy, and the
+    /// new multisig public key and signing policy.
+    ///
+    /// A policy is encoded as the number of clauses followed by, for every
+    /// clause, its name, threshold and number of members, and the key and
+    /// weight of every member. Counts, thresholds and weights are little
+    /// endian u32s, names and keys are prefixed with their length as a little
+    /// endian u32.
+    pub fn to_signing_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(KEY_ROTATION_CONTEXT);
+        buf.extend_from_slice(&self.from.public_key()?.to_vec());
+        put_policy(&mut buf, &self.from);
+        buf.extend_from_slice(&self.to.public_key()?.to_vec());
+        put_policy(&mut buf, &self.to);
+        Ok(buf.to_vec())
+    }
+
+    /// Returns the distinct members of the current key that validly signed
+    /// this rotation.
+    pub fn signers(&self) -> Result<Vec<PublicKey>> {
+        let msg = self.to_signing_bytes()?;
+        Ok(verified_signers(
+            &self.signatures,
+            &self.from.public_keys(),
+            &msg,
+        ))
+    }
+
+    /// Verifies that the rotation was signed by the required number of
+    /// members of the given current key, and satisfies its signing policy.
+    pub fn verify(&self, current: &PublicKey) -> Result<()> {
+        if &self.from.public_key()? != current {
+            return Err(Error::rotation(&format!(
+                "rotation is from {}, expected {current}",
+                self.from.public_key()?
+            )));
+        }
+        let signers = self.signers()?;
+        let policy = self.from.evaluate_policy(&signers);
+        if !policy.satisfied {
+            let unsatisfied: Vec<String> = policy
+                .clauses
+                .iter()
+                .filter(|clause| !clause.satisfied)
+                .map(|clause| {
+                    format!(
+                        "{} ({} of {})",
+                        clause.name, clause.weight, clause.threshold
+                    )
+                })
+                .collect();
+            return Err(Error::rotation(&format!(
+                "rotation to {} does not satisfy {}",
+                self.to.public_key()?,
+                unsatisfied.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn put_policy(buf: &mut BytesMut, manifest: &PublicKeyManifest) {
+    buf.put_u32_le(manifest.policy.len() as u32);
+    for clause in &manifest.policy {
+        put_field(buf, clause.name.as_bytes());
+        buf.put_u32_le(clause.threshold);
+        buf.put_u32_le(clause.members.len() as u32);
+        for member in &clause.members {
+            put_field(buf, &member.address().to_vec());
+            buf.put_u32_le(member.weight());
+        }
+    }
+}
+
+/// Follows a chain of rotations from the given pinned root key and returns
+/// the key the chain ends at.
+pub fn verify_chain(root: &PublicKey, chain: &[KeyRotation]) -> Result<PublicKey> {
+    let mut current = root.clone();
+    for rotation in chain {
+        rotation.verify(&current)?;
+        current = rotation.to.public_key()?;
+    }
+    Ok(current)
+}
@@ -0,0 +1,135 @@
+use crate::{Descriptor, Edges, ReasonCount};
+use helium_crypto::PublicKeyBinary;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+/// Statistics about the nodes and edges of a descriptor
+#[derive(Debug, Serialize)]
+pub struct DescriptorStats {
+    pub nodes: usize,
+    pub edges: usize,
+    pub edge_keys: usize,
+    /// The number of edge keys that are also full nodes. Their edges are
+    /// redundant since the full node already denies all of them.
+    pub edge_keys_with_node: usize,
+    /// The number of connected components in the edge graph
+    pub components: usize,
+    /// The number of keys in the largest connected component
+    pub largest_component: usize,
+    /// The number of edge keys per number of edges
+    pub degrees: BTreeMap<usize, usize>,
+    /// The edge keys with the most edges, most connected first
+    pub top: Vec<KeyDegree>,
+    pub reasons: BTreeMap<String, ReasonCount>,
+    pub carryover: BTreeMap<u32, ReasonCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyDegree {
+    pub key: PublicKeyBinary,
+    pub edges: usize,
+}
+
+impl DescriptorStats {
+    /// Collects statistics for the given descriptor, listing the given number
+    /// of most connected edge keys
+    pub fn new(descriptor: &Descriptor, top: usize) -> Self {
+        let no_edges = Edges::default();
+        let Edges { keys, edges } = descriptor.edges.as_ref().unwrap_or(&no_edges);
+
+        let mut key_degrees = vec![0usize; keys.len()];
+        let mut components = Components::new(keys.len());
+        for edge in edges {
+            key_degrees[edge.source as usize] += 1;
+            key_degrees[edge.target as usize] += 1;
+            components.join(edge.source as usize, edge.target as usize);
+        }
+
+        let mut degrees = BTreeMap::new();
+        for degree in &key_degrees {
+            *degrees.entry(*degree).or_default() += 1;
+        }
+        let mut ranked = key_degrees.iter().enumerate().collect::<Vec<_>>();
+        // A stable sort keeps keys with the same degree in descriptor order
+        ranked.sort_by(|(_, a), (_, b)| b.cmp(a));
+        let top = ranked
+            .into_iter()
+            .take(top)
+            .map(|(index, degree)| KeyDegree {
+                key: PublicKeyBinary::from(keys[index].as_slice()),
+                edges: *degree,
+            })
+            .collect();
+
+        let node_keys = descriptor
+            .nodes
+            .iter()
+            .map(|node| node.key.as_slice())
+            .collect::<HashSet<_>>();
+        let edge_keys_with_node = keys
+            .iter()
+            .filter(|key| node_keys.contains(key.as_slice()))
+            .count();
+
+        let mut carryover: BTreeMap<u32, ReasonCount> = BTreeMap::new();
+        for node in &descriptor.nodes {
+            carryover.entry(node.carryover).or_default().nodes += 1;
+        }
+        for edge in edges {
+            carryover.entry(edge.carryover).or_default().edges += 1;
+        }
+
+        let sizes = components.sizes();
+        Self {
+            nodes: descriptor.nodes.len(),
+            edges: edges.len(),
+            edge_keys: keys.len(),
+            edge_keys_with_node,
+            components: sizes.len(),
+            largest_component: sizes.into_iter().max().unwrap_or(0),
+            degrees,
+            top,
+            reasons: descriptor.reason_counts(),
+            carryover,
+        }
+    }
+}
+
+/// Disjoint sets of edge key indices, joined by edges
+struct Components {
+    parents: Vec<usize>,
+}
+
+impl Components {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn root(&mut self, mut index: usize) -> usize {
+        while self.parents[index] != index {
+            // Point every other index on the path to its grandparent
+            self.parents[index] = self.parents[self.parents[index]];
+            index = self.parents[index];
+        }
+        index
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.root(a), self.root(b));
+        if a != b {
+            self.parents[a] = b;
+        }
+    }
+
+    /// The number of indices in every component
+    fn sizes(mut self) -> Vec<usize> {
+        let mut sizes = BTreeMap::<usize, usize>::new();
+        for index in 0..self.parents.len() {
+            let root = self.root(index);
+            *sizes.entry(root).or_default() += 1;
+        }
+        sizes.into_values().collect()
+    }
+}
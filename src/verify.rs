@@ -0,0 +1,542 @@
+//! Filter verification and membership queries that build without std.
+//!
+//! This module only depends on `core` and works directly on the bytes of a
+//! filter file, so it can be used on devices that need to check a filter but
+//! can not pull in the generator. Build the crate with `default-features =
+//! false` to get just this module.
+use core::{fmt, hash::Hasher};
+use ed25519_compact as ed25519;
+use p256::{
+    ecdsa::{signature::Verifier, Signature as EccSignature, VerifyingKey},
+    elliptic_curve::DecompactPoint,
+    AffinePoint, FieldBytes,
+};
+use sha2::{Digest, Sha256};
+use twox_hash::XxHash64;
+
+pub const FILTTER_VERSION: u8 = 2;
+
+/// The filter version for filters whose signature is over the signing
+/// envelope rather than the full signing data. The filter data is encoded the
+/// same way as in version 2.
+pub const FILTER_ENVELOPE_VERSION: u8 = 3;
+
+/// Domain separation prefix for the filter signing envelope
+pub const FILTER_ENVELOPE_CONTEXT: &[u8] = b"xorf-generator/filter-envelope/v1";
+
+const ENVELOPE_LEN: usize = FILTER_ENVELOPE_CONTEXT.len() + 1 + 4 + 32 + 32;
+
+const KEYTYPE_ECC_COMPACT: u8 = 0x00;
+const KEYTYPE_ED25519: u8 = 0x01;
+const KEYTYPE_MULTISIG: u8 = 0x02;
+/// Length of an ecc_compact or ed25519 key including its key tag
+const MEMBER_KEY_LEN: usize = 33;
+/// Multihash prefix of a sha256 digest
+const SHA256_MULTIHASH: [u8; 2] = [0x12, 0x20];
+
+pub type Result<T = ()> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The filter bytes are truncated or otherwise not a valid encoding.
+    Malformed(&'static str),
+    /// The filter version is not known.
+    UnsupportedVersion(u8),
+    /// The key type of the given public key, or of one of the members of a
+    /// multisig key, is not supported.
+    UnsupportedKey(u8),
+    /// The signature does not verify against the given public key.
+    InvalidSignature,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(reason) => write!(f, "malformed filter: {reason}"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported filter version {version}"),
+            Self::UnsupportedKey(tag) => write!(f, "unsupported key type {tag:#04x}"),
+            Self::InvalidSignature => f.write_str("invalid signature"),
+        }
+    }
+}
+
+#[cfg(feature = "generator")]
+impl std::error::Error for Error {}
+
+/// A filter borrowed from the bytes of a filter file.
+///
+/// Parsing checks the header and the shape of the fingerprint array, but not
+/// the signature. Call [`FilterRef::verify`] before trusting membership
+/// answers.
+#[derive(Debug, Clone, Copy)]
+pub struct FilterRef<'a> {
+    version: u8,
+    signature: &'a [u8],
+    serial: u32,
+    signing_bytes: &'a [u8],
+    data_bytes: &'a [u8],
+    data: FilterDataRef<'a>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FilterDataRef<'a> {
+    Xor {
+        seed: u64,
+        block_length: usize,
+        fingerprints: &'a [u8],
+    },
+    BFuse {
+        seed: u64,
+        segment_length: u32,
+        segment_length_mask: u32,
+        segment_count_length: u32,
+        fingerprints: &'a [u8],
+    },
+}
+
+impl<'a> FilterRef<'a> {
+    /// Parses the bytes of a filter file without copying the fingerprints.
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self> {
+        let mut buf = Reader(data);
+        let version = buf.u8()?;
+        let signature_len = buf.u16()? as usize;
+        let signature = buf.take(signature_len)?;
+        let signing_bytes = buf.0;
+        let serial = buf.u32()?;
+        let data_bytes = buf.0;
+        let data = FilterDataRef::from_signing_bytes(&mut buf, version)?;
+        if !buf.0.is_empty() {
+            return Err(Error::Malformed("trailing data"));
+        }
+        Ok(Self {
+            version,
+            signature,
+            serial,
+            signing_bytes,
+            data_bytes,
+            data,
+        })
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    pub fn signature(&self) -> &'a [u8] {
+        self.signature
+    }
+
+    /// The serial and encoded filter data, as signed by version 1 and 2
+    /// filters.
+    pub fn signing_bytes(&self) -> &'a [u8] {
+        self.signing_bytes
+    }
+
+    /// The number of fingerprints in the filter
+    pub fn len(&self) -> usize {
+        self.data.fingerprints().len() / 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self.data {
+            FilterDataRef::Xor { .. } => "xor",
+            FilterDataRef::BFuse { .. } => "binary_fuse",
+        }
+    }
+
+    /// The sha256 hash of the signing bytes, as listed in filter manifests.
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(self.signing_bytes).into()
+    }
+
+    pub fn contains_hash(&self, hash: u64) -> bool {
+        self.data.contains(hash)
+    }
+
+    /// Checks whether the given binary public key is in the filter.
+    pub fn contains(&self, public_key: &[u8]) -> bool {
+        self.contains_hash(public_key_hash(public_key))
+    }
+
+    /// Checks whether the edge between the two given binary public keys is in
+    /// the filter.
+    pub fn contains_edge(&self, a: &[u8], b: &[u8]) -> bool {
+        self.contains_hash(edge_hash(a, b))
+    }
+
+    /// Verifies the filter signature against the given binary public key.
+    ///
+    /// Supported keys are ed25519, ecc_compact and multisig keys with ed25519
+    /// or ecc_compact members. A multisig signature needs valid signatures
+    /// from at least `m` distinct members, and its member keys must hash to
+    /// the digest in the multisig key.
+    pub fn verify(&self, public_key: &[u8]) -> Result {
+        let mut envelope = [0u8; ENVELOPE_LEN];
+        let msg = match self.version {
+            FILTER_ENVELOPE_VERSION => {
+                self.envelope(&mut envelope);
+                &envelope[..]
+            }
+            _ => self.signing_bytes,
+        };
+        verify_signature(public_key, msg, self.signature)
+    }
+
+    fn envelope(&self, buf: &mut [u8; ENVELOPE_LEN]) {
+        let (kind, fingerprint_bits) = match self.data {
+            FilterDataRef::Xor { .. } => (0u8, u32::BITS),
+            FilterDataRef::BFuse { .. } => (1u8, u32::BITS),
+        };
+        let metadata = Sha256::new()
+            .chain_update([kind])
+            .chain_update(fingerprint_bits.to_le_bytes())
+            .chain_update((self.len() as u64).to_le_bytes())
+            .finalize();
+
+        let (context, rest) = buf.split_at_mut(FILTER_ENVELOPE_CONTEXT.len());
+        context.copy_from_slice(FILTER_ENVELOPE_CONTEXT);
+        rest[0] = self.version;
+        rest[1..5].copy_from_slice(&self.serial.to_le_bytes());
+        rest[5..37].copy_from_slice(&Sha256::digest(self.data_bytes));
+        rest[37..69].copy_from_slice(&metadata);
+    }
+}
+
+impl<'a> FilterDataRef<'a> {
+    fn from_signing_bytes(buf: &mut Reader<'a>, version: u8) -> Result<Self> {
+        match version {
+            1 => Self::xor_from(buf),
+            FILTTER_VERSION | FILTER_ENVELOPE_VERSION => match buf.u32()? {
+                0 => Self::xor_from(buf),
+                1 => Self::bfuse_from(buf),
+                _ => Err(Error::Malformed("unknown filter kind")),
+            },
+            _ => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+
+    fn xor_from(buf: &mut Reader<'a>) -> Result<Self> {
+        let seed = buf.u64()?;
+        let block_length = usize::try_from(buf.u64()?)
+            .map_err(|_| Error::Malformed("block length out of range"))?;
+        let fingerprints = buf.fingerprints()?;
+        // Each lookup indexes one fingerprint in each of the three blocks
+        match block_length.checked_mul(3) {
+            Some(len) if len <= fingerprints.len() / 4 => (),
+            _ => return Err(Error::Malformed("fingerprints shorter than blocks")),
+        }
+        Ok(Self::Xor {
+            seed,
+            block_length,
+            fingerprints,
+        })
+    }
+
+    fn bfuse_from(buf: &mut Reader<'a>) -> Result<Self> {
+        let seed = buf.u64()?;
+        let segment_length = buf.u32()?;
+        let segment_length_mask = buf.u32()?;
+        let segment_count_length = buf.u32()?;
+        let fingerprints = buf.fingerprints()?;
+        if !segment_length.is_power_of_two() || segment_length_mask != segment_length - 1 {
+            return Err(Error::Malformed("invalid segment length"));
+        }
+        if segment_count_length == 0 || segment_count_length % segment_length != 0 {
+            return Err(Error::Malformed("invalid segment count length"));
+        }
+        // Lookups index into the segment a key hashes to and the two segments
+        // following it, so xorf lays out exactly two segments more than the
+        // segment count length. This also keeps every index within a u32.
+        let len = segment_count_length as u64 + 2 * segment_length as u64;
+        if len > u32::MAX as u64 || len != fingerprints.len() as u64 / 4 {
+            return Err(Error::Malformed("fingerprints do not match segments"));
+        }
+        Ok(Self::BFuse {
+            seed,
+            segment_length,
+            segment_length_mask,
+            segment_count_length,
+            fingerprints,
+        })
+    }
+
+    fn fingerprints(&self) -> &'a [u8] {
+        match self {
+            Self::Xor { fingerprints, .. } | Self::BFuse { fingerprints, .. } => fingerprints,
+        }
+    }
+
+    fn fingerprint(&self, index: usize) -> Option<u32> {
+        let offset = index.checked_mul(4)?;
+        let bytes = self.fingerprints().get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn contains(&self, key: u64) -> bool {
+        self.lookup(key).unwrap_or(false)
+    }
+
+    // Ports of the xorf `contains` implementations for 32 bit fingerprints.
+    // Indices are in range for a parsed filter, but are checked regardless.
+    fn lookup(&self, key: u64) -> Option<bool> {
+        match *self {
+            Self::Xor {
+                seed, block_length, ..
+            } => {
+                let hash = mix(key, seed);
+                let h = |i: u32| {
+                    let rot = hash.rotate_left(i * 21) as u32;
+                    ((rot as u64 * block_length as u64) >> 32) as usize
+                };
+                Some(
+                    fingerprint(hash)
+                        == self.fingerprint(h(0))?
+                            ^ self.fingerprint(h(1).checked_add(block_length)?)?
+                            ^ self.fingerprint(h(2).checked_add(block_length.checked_mul(2)?)?)?,
+                )
+            }
+            Self::BFuse {
+                seed,
+                segment_length,
+                segment_length_mask,
+                segment_count_length,
+                ..
+            } => {
+                let hash = mix(key, seed);
+                let h0 = ((hash as u128 * segment_count_length as u128) >> 64) as u32;
+                let h1 =
+                    h0.checked_add(segment_length)? ^ ((hash >> 18) as u32 & segment_length_mask);
+                let h2 = h0.checked_add(segment_length.checked_mul(2)?)?
+                    ^ (hash as u32 & segment_length_mask);
+                Some(
+                    fingerprint(hash)
+                        ^ self.fingerprint(h0 as usize)?
+                        ^ self.fingerprint(h1 as usize)?
+                        ^ self.fingerprint(h2 as usize)?
+                        == 0,
+                )
+            }
+        }
+    }
+}
+
+fn mix(key: u64, seed: u64) -> u64 {
+    let mut k = key.wrapping_add(seed);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+    k
+}
+
+fn fingerprint(hash: u64) -> u32 {
+    (hash ^ (hash >> 32)) as u32
+}
+
+/// The filter hash of a binary public key.
+pub fn public_key_hash(public_key: &[u8]) -> u64 {
+    let mut hasher = XxHash64::default();
+    hasher.write(public_key);
+    hasher.finish()
+}
+
+/// The filter hash of the edge between two binary public keys. The hash does
+/// not depend on the order of the keys.
+pub fn edge_hash(a: &[u8], b: &[u8]) -> u64 {
+    let (a, b) = if a < b { (a, b) } else { (b, a) };
+    let mut hasher = XxHash64::default();
+    hasher.write(a);
+    hasher.write(b);
+    hasher.finish()
+}
+
+/// Verifies a signature over the given message against a binary public key.
+pub fn verify_signature(public_key: &[u8], msg: &[u8], signature: &[u8]) -> Result {
+    let tag = *public_key.first().ok_or(Error::UnsupportedKey(0))?;
+    match tag & 0x0F {
+        KEYTYPE_MULTISIG => verify_multisig(public_key, msg, signature),
+        _ => verify_member(public_key, msg, signature),
+    }
+}
+
+fn verify_member(public_key: &[u8], msg: &[u8], signature: &[u8]) -> Result {
+    let tag = public_key[0];
+    if public_key.len() != MEMBER_KEY_LEN {
+        return Err(Error::UnsupportedKey(tag));
+    }
+    let key = &public_key[1..];
+    let verified = match tag & 0x0F {
+        KEYTYPE_ED25519 => {
+            let key =
+                ed25519::PublicKey::from_slice(key).map_err(|_| Error::UnsupportedKey(tag))?;
+            ed25519::Signature::from_slice(signature)
+                .map(|signature| key.verify(msg, &signature).is_ok())
+                .unwrap_or(false)
+        }
+        KEYTYPE_ECC_COMPACT => {
+            let point: Option<AffinePoint> =
+                AffinePoint::decompact(FieldBytes::from_slice(key)).into();
+            let key = point
+                .and_then(|point| p256::PublicKey::from_affine(point).ok())
+                .map(VerifyingKey::from)
+                .ok_or(Error::UnsupportedKey(tag))?;
+            EccSignature::from_der(signature)
+                .map(|signature| key.verify(msg, &signature).is_ok())
+                .unwrap_or(false)
+        }
+        _ => return Err(Error::UnsupportedKey(tag)),
+    };
+    if verified {
+        Ok(())
+    } else {
+        Err(Error::InvalidSignature)
+    }
+}
+
+fn verify_multisig(public_key: &[u8], msg: &[u8], signature: &[u8]) -> Result {
+    let signers = MultisigSigners::new(public_key, msg, signature)?;
+    if signers.count() >= signers.threshold() as usize {
+        Ok(())
+    } else {
+        Err(Error::InvalidSignature)
+    }
+}
+
+/// The members of a multisig key with a valid signature over a message.
+///
+/// A multisig public key is its key tag, m, n and the sha256 multihash of the
+/// concatenated member keys. The signature carries the n member keys followed
+/// by index, length and signature entries of the members that signed. Each
+/// member counts at most once, and entries that refer to a member that does
+/// not exist are ignored.
+#[derive(Debug, Clone, Copy)]
+pub struct MultisigSigners<'a> {
+    threshold: u8,
+    members: &'a [u8],
+    signed: [bool; 256],
+}
+
+impl<'a> MultisigSigners<'a> {
+    /// Checks the member keys in the signature against the digest of the
+    /// given multisig key and verifies the member signatures over the given
+    /// message.
+    pub fn new(public_key: &[u8], msg: &[u8], signature: &'a [u8]) -> Result<Self> {
+        let tag = *public_key.first().ok_or(Error::UnsupportedKey(0))?;
+        let (m, n, digest) = match public_key {
+            [_, m, n, rest @ ..]
+                if tag & 0x0F == KEYTYPE_MULTISIG
+                    && rest.len() == 34
+                    && rest[..2] == SHA256_MULTIHASH =>
+            {
+                (*m, *n, &rest[2..])
+            }
+            _ => return Err(Error::UnsupportedKey(tag)),
+        };
+
+        let mut buf = Reader(signature);
+        let members = buf
+            .take(n as usize * MEMBER_KEY_LEN)
+            .map_err(|_| Error::InvalidSignature)?;
+        let mut hasher = Sha256::new();
+        for member in members.chunks_exact(MEMBER_KEY_LEN) {
+            // Members must be on the network of the multisig key
+            if member[0] & 0xF0 != tag & 0xF0 {
+                return Err(Error::InvalidSignature);
+            }
+            hasher.update(member);
+        }
+        if hasher.finalize()[..] != digest[..] {
+            return Err(Error::InvalidSignature);
+        }
+
+        let mut signed = [false; 256];
+        while !buf.0.is_empty() {
+            let index = buf.u8().map_err(|_| Error::InvalidSignature)? as usize;
+            let len = buf.u8().map_err(|_| Error::InvalidSignature)? as usize;
+            let key_signature = buf.take(len).map_err(|_| Error::InvalidSignature)?;
+            if index >= n as usize || signed[index] {
+                continue;
+            }
+            let member = &members[index * MEMBER_KEY_LEN..(index + 1) * MEMBER_KEY_LEN];
+            signed[index] = verify_member(member, msg, key_signature).is_ok();
+        }
+        Ok(Self {
+            threshold: m,
+            members,
+            signed,
+        })
+    }
+
+    /// The number of member signatures the multisig key requires
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// The number of distinct members that signed
+    pub fn count(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// The binary public keys of the members that signed
+    pub fn iter(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.members
+            .chunks_exact(MEMBER_KEY_LEN)
+            .zip(self.signed)
+            .filter_map(|(member, signed)| signed.then_some(member))
+    }
+}
+
+/// A cursor over little endian encoded filter bytes
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::Malformed("truncated"));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    /// A bincode encoded slice of u32 fingerprints: a u64 length followed by
+    /// the fingerprints
+    fn fingerprints(&mut self) -> Result<&'a [u8]> {
+        let len = usize::try_from(self.u64()?)
+            .ok()
+            .and_then(|len| len.checked_mul(4))
+            .ok_or(Error::Malformed("fingerprint count out of range"))?;
+        self.take(len)
+    }
+}
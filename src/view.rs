@@ -0,0 +1,78 @@
+use crate::{verify::FilterRef, Result};
+use helium_crypto::{PublicKey, PublicKeyBinary};
+use memmap2::Mmap;
+use std::{fs::File, path::Path};
+
+/// A filter that is memory mapped from a filter file rather than read into
+/// memory.
+///
+/// The header and signature are checked once when the file is opened, after
+/// which membership queries read fingerprints straight from the mapping.
+/// Processes that map the same file share its pages in the page cache.
+///
+/// The filter file must not be modified in place while it is mapped. Replace
+/// filters by writing a new file and renaming it over the old one.
+pub struct FilterView {
+    // Declared before the mapping it borrows so it is dropped first
+    filter: FilterRef<'static>,
+    _map: Mmap,
+}
+
+impl FilterView {
+    /// Maps the filter file at the given path and verifies its signature
+    /// against the given public key.
+    pub fn open<P: AsRef<Path>>(path: P, public_key: &PublicKey) -> Result<Self> {
+        let view = Self::open_unverified(path)?;
+        view.filter().verify(&public_key.to_vec())?;
+        Ok(view)
+    }
+
+    /// Maps the filter file at the given path and checks its header without
+    /// verifying its signature.
+    pub fn open_unverified<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the mapping is read only and callers are required to not
+        // modify the file in place while it is mapped
+        let map = unsafe { Mmap::map(&file)? };
+        // Safety: the mapped memory does not move when the mapping is moved
+        // and stays valid until the mapping is dropped. The filter is never
+        // handed out for longer than the view is borrowed.
+        let data: &'static [u8] = unsafe { std::slice::from_raw_parts(map.as_ptr(), map.len()) };
+        let filter = FilterRef::from_bytes(data)?;
+        Ok(Self { filter, _map: map })
+    }
+
+    /// The filter borrowed from the mapped file
+    pub fn filter(&self) -> FilterRef<'_> {
+        self.filter
+    }
+
+    pub fn version(&self) -> u8 {
+        self.filter().version()
+    }
+
+    pub fn serial(&self) -> u32 {
+        self.filter().serial()
+    }
+
+    pub fn len(&self) -> usize {
+        self.filter().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filter().is_empty()
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        self.filter().hash().to_vec()
+    }
+
+    pub fn contains(&self, public_key: &PublicKeyBinary) -> bool {
+        self.filter().contains(public_key.as_ref())
+    }
+
+    pub fn contains_edge(&self, source: &PublicKeyBinary, target: &PublicKeyBinary) -> bool {
+        self.filter()
+            .contains_edge(source.as_ref(), target.as_ref())
+    }
+}
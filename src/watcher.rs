@@ -0,0 +1,318 @@
+use crate::{Error, Filter, Result};
+use helium_crypto::PublicKey;
+use sha2::{Digest, Sha256};
+use std::{
+    any::Any,
+    cmp::Reverse,
+    collections::HashMap,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+/// The coarsest modification time resolution of common file systems
+const MTIME_RESOLUTION: Duration = Duration::from_secs(2);
+
+type AcceptFn = Box<dyn Fn(&Path, &Filter) + Send>;
+type RejectFn = Box<dyn Fn(&Path, &Error) + Send>;
+
+/// Watches a filter file, or a directory of filter files, and swaps in newer
+/// filters as they appear.
+///
+/// A new filter is only accepted if it verifies against the pinned public key
+/// and its serial is higher than the serial of the current filter. The
+/// current filter is shared behind an `Arc`, so readers keep using the filter
+/// they have while a new one is swapped in.
+pub struct FilterWatcher {
+    current: Arc<RwLock<Arc<Filter>>>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Configures and starts a [`FilterWatcher`].
+pub struct FilterWatcherBuilder {
+    path: PathBuf,
+    public_key: PublicKey,
+    interval: Duration,
+    on_accept: Option<AcceptFn>,
+    on_reject: Option<RejectFn>,
+}
+
+impl FilterWatcher {
+    /// Creates a builder for a watcher on the given filter file or directory
+    /// that verifies filters against the given public key. In a directory
+    /// every file with a `.bin` extension is considered a filter.
+    pub fn builder<P: AsRef<Path>>(path: P, public_key: PublicKey) -> FilterWatcherBuilder {
+        FilterWatcherBuilder {
+            path: path.as_ref().to_path_buf(),
+            public_key,
+            interval: Duration::from_secs(5),
+            on_accept: None,
+            on_reject: None,
+        }
+    }
+
+    /// The current filter
+    pub fn current(&self) -> Arc<Filter> {
+        self.current
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Whether the watcher is still checking for new filters
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+impl Drop for FilterWatcher {
+    fn drop(&mut self) {
+        // Dropping the sender wakes up and stops the polling thread
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl FilterWatcherBuilder {
+    /// How often to check for new filters. Defaults to 5 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Called with the path and filter of every accepted filter, including
+    /// the initial one.
+    pub fn on_accept<F: Fn(&Path, &Filter) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_accept = Some(Box::new(f));
+        self
+    }
+
+    /// Called with the path of every rejected filter file and the reason it
+    /// was rejected.
+    pub fn on_reject<F: Fn(&Path, &Error) + Send + 'static>(mut self, f: F) -> Self {
+        self.on_reject = Some(Box::new(f));
+        self
+    }
+
+    /// Loads the newest valid filter and starts watching for newer ones.
+    /// Fails if no valid filter is found.
+    pub fn start(self) -> Result<FilterWatcher> {
+        let mut poller = Poller {
+            path: self.path,
+            public_key: self.public_key,
+            on_accept: self.on_accept,
+            on_reject: self.on_reject,
+            current: None,
+            seen: HashMap::new(),
+        };
+        poller.poll();
+        let current = poller
+            .current
+            .clone()
+            .map(|filter| Arc::new(RwLock::new(filter)))
+            .ok_or_else(|| {
+                Error::filter(&format!("No valid filter in {}", poller.path.display()))
+            })?;
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let shared = current.clone();
+        let interval = self.interval;
+        let thread = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // A panic, for example in a callback, is reported and does
+                // not stop the watcher
+                match panic::catch_unwind(AssertUnwindSafe(|| poller.poll())) {
+                    Ok(Some(filter)) => {
+                        *shared.write().unwrap_or_else(|err| err.into_inner()) = filter
+                    }
+                    Ok(None) => (),
+                    Err(panic) => {
+                        let err = Error::filter(&format!(
+                            "Checking for filters panicked: {}",
+                            panic_message(&*panic)
+                        ));
+                        let path = poller.path.clone();
+                        let _ =
+                            panic::catch_unwind(AssertUnwindSafe(|| poller.reject(&path, &err)));
+                    }
+                }
+            }
+        });
+        Ok(FilterWatcher {
+            current,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+struct Poller {
+    path: PathBuf,
+    public_key: PublicKey,
+    on_accept: Option<AcceptFn>,
+    on_reject: Option<RejectFn>,
+    current: Option<Arc<Filter>>,
+    /// Every file as it was when it was last checked
+    seen: HashMap<PathBuf, Seen>,
+}
+
+/// The state of a filter file when it was last read
+struct Seen {
+    stamp: Stamp,
+    hash: Vec<u8>,
+    read_at: SystemTime,
+}
+
+/// The modification time, size and inode of a file
+type Stamp = (SystemTime, u64, u64);
+
+fn stamp(metadata: &fs::Metadata) -> Stamp {
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+    (
+        metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        metadata.len(),
+        inode,
+    )
+}
+
+impl Poller {
+    /// Checks all new or changed filter files and returns the newly accepted
+    /// filter, if any. When several new filters are found only the one with
+    /// the highest serial is accepted, and the others are skipped. On the
+    /// first check this skips the older filters in a directory.
+    fn poll(&mut self) -> Option<Arc<Filter>> {
+        let mut candidates = vec![];
+        for (path, data) in self.changed_files() {
+            let verified = panic::catch_unwind(|| {
+                let filter = Filter::from_bytes(&data)?;
+                filter.verify(&self.public_key)?;
+                Ok(filter)
+            })
+            .unwrap_or_else(|panic| {
+                Err(Error::filter(&format!(
+                    "Verifying filter panicked: {}",
+                    panic_message(&*panic)
+                )))
+            });
+            match verified {
+                Ok(filter) => candidates.push((path, filter)),
+                Err(err) => self.reject(&path, &err),
+            }
+        }
+        candidates.sort_by_key(|(_, filter)| Reverse(filter.serial));
+
+        let mut accepted = None;
+        for (path, filter) in candidates {
+            match self.check_newer(&filter) {
+                Ok(true) if accepted.is_none() => {
+                    if let Some(on_accept) = &self.on_accept {
+                        on_accept(&path, &filter);
+                    }
+                    accepted = Some(Arc::new(filter));
+                }
+                Ok(_) => (),
+                Err(err) => self.reject(&path, &err),
+            }
+        }
+        if let Some(filter) = &accepted {
+            self.current = Some(filter.clone());
+        }
+        accepted
+    }
+
+    fn reject(&self, path: &Path, err: &Error) {
+        if let Some(on_reject) = &self.on_reject {
+            on_reject(path, err);
+        }
+    }
+
+    /// Checks that the given filter is newer than the current filter. A copy
+    /// of the current filter is not newer, but is not an error either.
+    fn check_newer(&self, filter: &Filter) -> Result<bool> {
+        let Some(current) = &self.current else {
+            return Ok(true);
+        };
+        if filter.serial == current.serial && filter.hash()? == current.hash()? {
+            return Ok(false);
+        }
+        if filter.serial <= current.serial {
+            return Err(Error::filter(&format!(
+                "Filter serial {} is not newer than current serial {}",
+                filter.serial, current.serial
+            )));
+        }
+        Ok(true)
+    }
+
+    /// Returns the filter files that were added or modified since they were
+    /// last checked, with their contents, in path order.
+    ///
+    /// A file whose modification time, size and inode did not change is only
+    /// read again if it was modified around the time it was last read, since
+    /// a rewrite within the resolution of the modification time would go
+    /// unnoticed otherwise. Files that are read are compared by their hash.
+    fn changed_files(&mut self) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut paths = if self.path.is_dir() {
+            fs::read_dir(&self.path)
+                .map(|entries| {
+                    entries
+                        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                        .filter(|path| path.extension().is_some_and(|ext| ext == "bin"))
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            vec![self.path.clone()]
+        };
+        paths.sort();
+        let mut changed = vec![];
+        for path in paths {
+            let read_at = SystemTime::now();
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let stamp = stamp(&metadata);
+            if let Some(seen) = self.seen.get(&path) {
+                if seen.stamp == stamp && stamp.0 + MTIME_RESOLUTION < seen.read_at {
+                    continue;
+                }
+            }
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+            let hash = Sha256::digest(&data).to_vec();
+            let unchanged = self.seen.get(&path).is_some_and(|seen| seen.hash == hash);
+            self.seen.insert(
+                path.clone(),
+                Seen {
+                    stamp,
+                    hash,
+                    read_at,
+                },
+            );
+            if !unchanged {
+                changed.push((path, data));
+            }
+        }
+        changed
+    }
+}
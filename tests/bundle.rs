@@ -0,0 +1,149 @@
+use helium_crypto::Keypair;
+use std::collections::HashMap;
+use xorf_generator::{
+    Artifact, Bundle, DescriptorBuilder, DescriptorCompression, Filter, Manifest,
+    PublicKeyManifest, BUNDLE_INDEX,
+};
+
+mod common;
+use common::{binary, keypair, multisig_sign};
+
+/// The artifacts of a filter with the given serial for a descriptor of the
+/// given hotspots, signed by the given member
+fn artifacts(serial: u32, hotspots: &[Keypair], member: &Keypair) -> HashMap<Artifact, Vec<u8>> {
+    let csv = hotspots
+        .iter()
+        .map(|hotspot| format!("{},,fraud,0\n", binary(hotspot)))
+        .collect::<String>();
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    let (descriptor, _) = builder.build().unwrap();
+    let mut descriptor_bytes = vec![];
+    descriptor
+        .to_writer(&mut descriptor_bytes, DescriptorCompression::default())
+        .unwrap();
+
+    let key_manifest = PublicKeyManifest {
+        public_keys: vec![member.public_key().clone().into()],
+        required: 1,
+        policy: vec![],
+    };
+    let mut filter = Filter::from_descriptor(serial, &descriptor).unwrap();
+    let manifest = Manifest::for_filter(&filter, &key_manifest).unwrap();
+    let msg = filter.signed_message().unwrap();
+    filter.signature = multisig_sign(&msg, std::slice::from_ref(member), 1);
+
+    HashMap::from([
+        (Artifact::Descriptor, descriptor_bytes),
+        (Artifact::Data, filter.to_signing_bytes().unwrap()),
+        (Artifact::Manifest, serde_json::to_vec(&manifest).unwrap()),
+        (
+            Artifact::PublicKey,
+            serde_json::to_vec(&key_manifest).unwrap(),
+        ),
+        (Artifact::Filter, filter.to_bytes().unwrap()),
+    ])
+}
+
+fn pack(bundle: &Bundle) -> Vec<u8> {
+    let mut data = vec![];
+    bundle.to_writer(&mut data).unwrap();
+    data
+}
+
+/// A tar archive with the given entries
+fn archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(vec![]);
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, *data).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn bundle_round_trip() {
+    let hotspots = [keypair(), keypair()];
+    let member = keypair();
+    let files = artifacts(4, &hotspots, &member);
+    let bundle = Bundle::new(files.clone()).unwrap();
+    assert_eq!(bundle.serial(), 4);
+    let verify = bundle.verify();
+    assert!(verify.is_verified(), "{:?}", verify.failed());
+
+    let packed = pack(&bundle);
+    // packing is deterministic
+    assert_eq!(pack(&Bundle::new(files.clone()).unwrap()), packed);
+    let unpacked = Bundle::from_reader(packed.as_slice()).unwrap();
+    assert_eq!(unpacked.serial(), 4);
+    for artifact in Artifact::ALL {
+        assert_eq!(unpacked.get(artifact), Some(files[&artifact].as_slice()));
+    }
+    assert!(unpacked.verify().is_verified());
+}
+
+#[test]
+fn bundle_detects_tampering() {
+    let hotspots = [keypair(), keypair()];
+    let member = keypair();
+    let files = artifacts(4, &hotspots, &member);
+    let other = artifacts(4, &hotspots[..1], &keypair());
+    let next = artifacts(5, &hotspots, &member);
+
+    // artifacts that do not belong together fail their checks even with a
+    // matching index
+    for (artifact, replacement, check) in [
+        (Artifact::Descriptor, &other, "descriptor"),
+        (Artifact::Manifest, &next, "manifest"),
+        (Artifact::PublicKey, &other, "filter"),
+        (Artifact::Filter, &next, "filter"),
+    ] {
+        let mut files = files.clone();
+        files.insert(artifact, replacement[&artifact].clone());
+        let verify = Bundle::new(files).unwrap().verify();
+        assert_eq!(verify.failed(), vec![check], "{artifact:?}");
+    }
+
+    // artifacts changed after packing no longer match the index
+    let bundle = Bundle::new(files.clone()).unwrap();
+    let index = serde_json::to_vec(&bundle.index).unwrap();
+    let mut entries = vec![(BUNDLE_INDEX, index.as_slice())];
+    for artifact in Artifact::ALL {
+        let data = if artifact == Artifact::Descriptor {
+            &other[&artifact]
+        } else {
+            &files[&artifact]
+        };
+        entries.push((artifact.file_name(), data.as_slice()));
+    }
+    let unpacked = Bundle::from_reader(archive(&entries).as_slice()).unwrap();
+    assert_eq!(unpacked.verify().failed(), vec!["index", "descriptor"]);
+}
+
+#[test]
+fn bundle_reader_rejects_unexpected_entries() {
+    let files = artifacts(4, &[keypair()], &keypair());
+    let bundle = Bundle::new(files.clone()).unwrap();
+    let index = serde_json::to_vec(&bundle.index).unwrap();
+    let data = files[&Artifact::Data].as_slice();
+
+    let duplicate_index = archive(&[(BUNDLE_INDEX, &index), (BUNDLE_INDEX, &index)]);
+    let duplicate = archive(&[
+        (BUNDLE_INDEX, &index),
+        ("data.bin", data),
+        ("data.bin", data),
+    ]);
+    let unexpected = archive(&[(BUNDLE_INDEX, &index), ("other.bin", data)]);
+    let missing_index = archive(&[("data.bin", data)]);
+    for archive in [duplicate_index, duplicate, unexpected, missing_index] {
+        assert!(Bundle::from_reader(archive.as_slice()).is_err());
+    }
+
+    // a bundle without every artifact can be read but does not verify
+    let partial = archive(&[(BUNDLE_INDEX, &index), ("data.bin", data)]);
+    let verify = Bundle::from_reader(partial.as_slice()).unwrap().verify();
+    assert!(!verify.is_verified());
+}
@@ -0,0 +1,598 @@
+use helium_crypto::{Keypair, PublicKeyBinary, Sign};
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use xorf_generator::{
+    base64_serde, xorf::Xor32, Descriptor, DescriptorBuilder, DescriptorCompression, Filter,
+    Manifest, PublicKeyManifest, FILTTER_VERSION,
+};
+
+mod common;
+use common::{binary, keypair, multisig_sign};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("xorf-generator-cli-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the command line tool in the given directory
+fn output(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_xorf-generator"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs the command line tool in the given directory and returns its exit
+/// code and stderr
+fn run(dir: &Path, args: &[&str]) -> (i32, String) {
+    let output = output(dir, args);
+    (
+        output.status.code().unwrap(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+/// Runs the command line tool in the given directory and returns its JSON
+/// output
+fn run_json(dir: &Path, args: &[&str]) -> serde_json::Value {
+    let output = output(dir, args);
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+/// A key manifest for the given members requiring one signature
+fn key_manifest(members: &[Keypair]) -> PublicKeyManifest {
+    PublicKeyManifest {
+        public_keys: members
+            .iter()
+            .map(|member| member.public_key().clone().into())
+            .collect(),
+        required: 1,
+        policy: vec![],
+    }
+}
+
+/// Sets the signature of the given member in the manifest file
+fn set_signature(dir: &Path, member: &Keypair, signature: &[u8]) {
+    let path = dir.join("manifest.json");
+    let mut json: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    let address = member.public_key().to_string();
+    for entry in json["signatures"].as_array_mut().unwrap() {
+        if entry["address"] == address.as_str() {
+            entry["signature"] = base64_serde::encode(signature).into();
+        }
+    }
+    fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+}
+
+#[test]
+fn manifest_verify_exit_codes() {
+    let dir = temp_dir("manifest-verify");
+    let members = [keypair(), keypair()];
+    let key_manifest = key_manifest(&members);
+    let filter = Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+    let manifest = Manifest::for_filter(&filter, &key_manifest).unwrap();
+    fs::write(dir.join("data.bin"), filter.to_signing_bytes().unwrap()).unwrap();
+    fs::write(
+        dir.join("public_key.json"),
+        serde_json::to_vec(&key_manifest).unwrap(),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("manifest.json"),
+        serde_json::to_vec(&manifest).unwrap(),
+    )
+    .unwrap();
+
+    // No member signed yet
+    let (code, stderr) = run(&dir, &["--output", "json", "manifest", "verify"]);
+    assert_eq!(code, 14);
+    let json: serde_json::Value = serde_json::from_str(&stderr).unwrap();
+    assert_eq!(json["error"]["code"], "policy_unsatisfied");
+
+    let msg = filter.signed_message().unwrap();
+    set_signature(&dir, &members[0], &members[0].sign(&msg).unwrap());
+    assert_eq!(run(&dir, &["manifest", "verify"]).0, 0);
+
+    // A signature that does not verify fails even though the policy is met
+    set_signature(&dir, &members[1], &members[1].sign(b"other").unwrap());
+    assert_eq!(run(&dir, &["manifest", "verify"]).0, 11);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// A child process that is killed when dropped
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Sends an HTTP request and returns the response status and JSON body,
+/// retrying the connection until the server listens
+fn request(addr: SocketAddr, method: &str, path: &str, body: &[u8]) -> (u16, serde_json::Value) {
+    let started = Instant::now();
+    let mut stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                thread::sleep(Duration::from_millis(50))
+            }
+            Err(err) => panic!("connecting to {addr}: {err}"),
+        }
+    };
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .unwrap();
+    // The server may answer before reading a body it refuses
+    let _ = stream.write_all(body);
+    let mut response = vec![];
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+fn write_descriptor(path: &Path, keys: &[PublicKeyBinary]) -> Descriptor {
+    let csv = keys
+        .iter()
+        .map(|key| format!("{key},,fraud,0\n"))
+        .collect::<String>();
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    let (descriptor, _) = builder.build().unwrap();
+    let mut file = fs::File::create(path).unwrap();
+    descriptor
+        .to_writer(&mut file, DescriptorCompression::default())
+        .unwrap();
+    descriptor
+}
+
+#[test]
+fn serve_routes() {
+    let dir = temp_dir("serve");
+    let member = keypair();
+    let key_manifest = key_manifest(std::slice::from_ref(&member));
+    fs::write(
+        dir.join("public_key.json"),
+        serde_json::to_vec(&key_manifest).unwrap(),
+    )
+    .unwrap();
+    let (listed, other, added) = (binary(&keypair()), binary(&keypair()), binary(&keypair()));
+    let descriptor = write_descriptor(
+        &dir.join("descriptor.bin.gz"),
+        std::slice::from_ref(&listed),
+    );
+    let mut filter = Filter::from_descriptor(7, &descriptor).unwrap();
+    let msg = filter.signed_message().unwrap();
+    filter.signature = multisig_sign(&msg, std::slice::from_ref(&member), 1);
+    fs::write(dir.join("filter.bin"), filter.to_bytes().unwrap()).unwrap();
+
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_xorf-generator"))
+            .current_dir(&dir)
+            .args([
+                "serve",
+                "--descriptor",
+                "descriptor.bin.gz",
+                "--interval",
+                "1",
+            ])
+            .args(["--listen", &addr.to_string()])
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    let (status, json) = request(addr, "GET", &format!("/contains/{listed}"), b"");
+    assert_eq!(status, 200);
+    assert_eq!(json["in_filter"], true);
+    let (_, json) = request(addr, "GET", &format!("/contains/{other}"), b"");
+    assert_eq!(json["in_filter"], false);
+    let (status, json) = request(addr, "GET", &format!("/contains/{listed}/{other}"), b"");
+    assert_eq!(status, 200);
+    assert_eq!(json["target"], other.to_string());
+
+    let queries = serde_json::to_vec(&serde_json::json!([
+        listed.to_string(),
+        other.to_string(),
+        [listed.to_string(), other.to_string()],
+    ]))
+    .unwrap();
+    let (status, json) = request(addr, "POST", "/contains", &queries);
+    assert_eq!(status, 200);
+    let results = json.as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["in_filter"], true);
+    assert_eq!(results[1]["in_filter"], false);
+
+    let (status, json) = request(addr, "GET", "/info", b"");
+    assert_eq!(status, 200);
+    assert_eq!(json["serial"], 7);
+    assert_eq!(json["verified"], true);
+
+    let (status, json) = request(addr, "GET", &format!("/descriptor/{listed}"), b"");
+    assert_eq!(status, 200);
+    assert_eq!(json["node"]["reason"], "fraud");
+
+    let (status, json) = request(addr, "GET", "/contains/invalid", b"");
+    assert_eq!(status, 400);
+    assert_eq!(json["error"]["code"], "decode");
+    let (status, json) = request(addr, "GET", "/unknown", b"");
+    assert_eq!(status, 404);
+    assert_eq!(json["error"]["code"], "not_found");
+    let (status, json) = request(addr, "POST", "/contains", &vec![b' '; (1 << 20) + 1]);
+    assert_eq!(status, 413);
+    assert_eq!(json["error"]["code"], "too_large");
+
+    // A changed descriptor is picked up without a restart
+    let (_, json) = request(addr, "GET", &format!("/descriptor/{added}"), b"");
+    assert!(json.get("node").is_none());
+    write_descriptor(&dir.join("descriptor.bin.gz"), &[listed, added.clone()]);
+    let started = Instant::now();
+    loop {
+        let (_, json) = request(addr, "GET", &format!("/descriptor/{added}"), b"");
+        if json.get("node").is_some() {
+            break;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn pipeline_resumes_after_signing() {
+    let dir = temp_dir("pipeline");
+    let job_dir = dir.join("job");
+    fs::create_dir_all(&job_dir).unwrap();
+    let members = [keypair(), keypair()];
+    let key_manifest = key_manifest(&members);
+    fs::write(
+        job_dir.join("public_key.json"),
+        serde_json::to_vec(&key_manifest).unwrap(),
+    )
+    .unwrap();
+    let listed = binary(&keypair());
+    fs::write(job_dir.join("hotspots.csv"), format!("{listed},,fraud,0\n")).unwrap();
+    fs::write(
+        job_dir.join("job.toml"),
+        "csv = \"hotspots.csv\"\nserial = 3\n",
+    )
+    .unwrap();
+    // paths in the job file are relative to the job file
+    let work_dir = job_dir.join("work");
+
+    let status = run_json(&dir, &["pipeline", "status", "job/job.toml"]);
+    assert_eq!(status["next"], "descriptor");
+
+    let status = run_json(&dir, &["pipeline", "run", "job/job.toml"]);
+    assert_eq!(status["next"], "signatures");
+    assert_eq!(status["steps"][3]["state"], "waiting");
+    for name in ["descriptor.bin.gz", "data.bin", "manifest.json"] {
+        assert!(work_dir.join(name).exists());
+    }
+    assert!(!work_dir.join("filter.bin").exists());
+
+    // running again without signatures keeps waiting
+    let status = run_json(&dir, &["pipeline", "run", "job/job.toml"]);
+    assert_eq!(status["next"], "signatures");
+
+    let filter = Filter::from_signing_path(&work_dir.join("data.bin"), FILTTER_VERSION).unwrap();
+    assert_eq!(filter.serial, 3);
+    let msg = filter.signed_message().unwrap();
+    set_signature(&work_dir, &members[1], &members[1].sign(&msg).unwrap());
+
+    let status = run_json(&dir, &["pipeline", "run", "job/job.toml"]);
+    assert!(status["next"].is_null());
+    let filter_path = work_dir.join("filter.bin");
+    let filter = Filter::from_path(&filter_path).unwrap();
+    filter.verify_with_policy(&key_manifest).unwrap();
+    assert!(filter.contains(&listed));
+
+    // finished steps are not run again
+    let bytes = fs::read(&filter_path).unwrap();
+    run_json(&dir, &["pipeline", "run", "job/job.toml"]);
+    assert_eq!(fs::read(&filter_path).unwrap(), bytes);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// Writes a detached signature file with the given name
+fn write_detached(
+    dir: &Path,
+    name: &str,
+    member: &Keypair,
+    manifest: &Manifest,
+    serial: u32,
+    signature: &[u8],
+) {
+    let json = serde_json::json!({
+        "address": member.public_key().to_string(),
+        "hash": manifest.hash,
+        "serial": serial,
+        "signature": base64_serde::encode(signature),
+    });
+    fs::write(dir.join(name), serde_json::to_vec(&json).unwrap()).unwrap();
+}
+
+#[test]
+fn manifest_collect_reports_rejected_signatures() {
+    let dir = temp_dir("manifest-collect");
+    let signatures = dir.join("signatures");
+    fs::create_dir_all(&signatures).unwrap();
+    let members = [keypair(), keypair(), keypair()];
+    let key_manifest = key_manifest(&members);
+    let filter = Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+    let manifest = Manifest::for_filter(&filter, &key_manifest).unwrap();
+    fs::write(dir.join("data.bin"), filter.to_signing_bytes().unwrap()).unwrap();
+    fs::write(
+        dir.join("public_key.json"),
+        serde_json::to_vec(&key_manifest).unwrap(),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("manifest.json"),
+        serde_json::to_vec(&manifest).unwrap(),
+    )
+    .unwrap();
+
+    let msg = filter.signed_message().unwrap();
+    let sign = |member: &Keypair| member.sign(&msg).unwrap();
+    let name = |member: &Keypair| format!("sig-{}.json", member.public_key());
+    // the same signature in two files
+    write_detached(
+        &signatures,
+        &name(&members[0]),
+        &members[0],
+        &manifest,
+        1,
+        &sign(&members[0]),
+    );
+    let copy = format!("sig-{}.copy.json", members[0].public_key());
+    write_detached(
+        &signatures,
+        &copy,
+        &members[0],
+        &manifest,
+        1,
+        &sign(&members[0]),
+    );
+    // a signature for the previous serial
+    write_detached(
+        &signatures,
+        &name(&members[1]),
+        &members[1],
+        &manifest,
+        0,
+        &sign(&members[1]),
+    );
+    let invalid = members[2].sign(b"other").unwrap();
+    write_detached(
+        &signatures,
+        &name(&members[2]),
+        &members[2],
+        &manifest,
+        1,
+        &invalid,
+    );
+    fs::write(signatures.join("notes.txt"), "not a signature").unwrap();
+
+    let json = run_json(&dir, &["manifest", "collect", "signatures"]);
+    assert_eq!(json["merged"], 1);
+    assert_eq!(json["rejected"], 3);
+    let statuses = json["signatures"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| (entry["address"].clone(), entry["status"].clone()))
+        .collect::<Vec<_>>();
+    let status = |member: &Keypair, status: &str| {
+        let address = serde_json::Value::from(member.public_key().to_string());
+        statuses
+            .iter()
+            .filter(|entry| entry.0 == address && entry.1 == status)
+            .count()
+    };
+    assert_eq!(status(&members[0], "merged"), 1);
+    assert_eq!(status(&members[0], "duplicate"), 1);
+    assert_eq!(status(&members[1], "stale"), 1);
+    assert_eq!(status(&members[2], "invalid"), 1);
+
+    let collected = Manifest::from_path(dir.join("manifest.json")).unwrap();
+    let signed = collected
+        .signatures
+        .iter()
+        .filter(|signature| signature.is_signed())
+        .collect::<Vec<_>>();
+    assert_eq!(signed.len(), 1);
+    assert_eq!(signed[0].address(), members[0].public_key());
+    assert_eq!(run(&dir, &["manifest", "verify"]).0, 0);
+
+    // collecting again merges nothing and leaves the manifest as it is
+    let before = fs::read(dir.join("manifest.json")).unwrap();
+    let json = run_json(&dir, &["manifest", "collect", "signatures"]);
+    assert_eq!(json["merged"], 0);
+    assert_eq!(json["rejected"], 4);
+    assert_eq!(fs::read(dir.join("manifest.json")).unwrap(), before);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// Runs the command line tool in the given directory with the given stdin
+fn pipe(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_xorf-generator"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn stdio_pipes_steps() {
+    let dir = temp_dir("stdio");
+    let member = keypair();
+    fs::write(
+        dir.join("public_key.json"),
+        serde_json::to_vec(&key_manifest(std::slice::from_ref(&member))).unwrap(),
+    )
+    .unwrap();
+    let listed = binary(&keypair());
+    fs::write(dir.join("hotspots.csv"), format!("{listed},,fraud,0\n")).unwrap();
+
+    // the report goes to stderr when the output goes to stdout
+    let output = pipe(&dir, &["descriptor", "generate", "hotspots.csv", "-"], b"");
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert!(report.is_object());
+    let descriptor = Descriptor::from_reader(output.stdout.as_slice()).unwrap();
+    assert!(descriptor.find_node(&listed).is_some());
+
+    let output = pipe(
+        &dir,
+        &["data", "generate", "-s", "5", "-", "-"],
+        &output.stdout,
+    );
+    assert!(output.status.success());
+    let filter = Filter::from_descriptor(5, &descriptor).unwrap();
+    assert_eq!(output.stdout, filter.to_signing_bytes().unwrap());
+
+    let data = output.stdout;
+    let output = pipe(&dir, &["manifest", "generate", "-d", "-", "-m", "-"], &data);
+    assert!(output.status.success());
+    let manifest: Manifest = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(manifest.serial, 5);
+    assert_eq!(manifest.hash, base64_serde::encode(&filter.hash().unwrap()));
+
+    // only one input can be read from stdin and one output written to stdout
+    let output = pipe(&dir, &["manifest", "generate", "-d", "-", "-k", "-"], &data);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only one input"));
+    let output = pipe(
+        &dir,
+        &[
+            "key",
+            "rotate",
+            "-n",
+            "public_key.json",
+            "-o",
+            "-",
+            "-d",
+            "-",
+        ],
+        b"",
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only one output"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn config_profiles_and_paths() {
+    let dir = temp_dir("config");
+    let conf = dir.join("conf");
+    fs::create_dir_all(conf.join("mainnet")).unwrap();
+    let manifest = dir.join("shared").join("manifest.json");
+    fs::write(
+        conf.join("xorf-generator.toml"),
+        format!(
+            r#"
+profile = "testnet"
+compression = "zstd"
+
+[profiles.mainnet]
+dir = "mainnet"
+network = "mainnet"
+kind = "binary_fuse"
+manifest = "{}"
+
+[profiles.testnet]
+dir = "testnet"
+network = "testnet"
+key = "keys/testnet.json"
+compression = "none"
+"#,
+            manifest.display()
+        ),
+    )
+    .unwrap();
+    let config = ["--config", "conf/xorf-generator.toml"];
+    let show = |args: &[&str]| {
+        let args = config.iter().chain(args).copied().collect::<Vec<_>>();
+        run_json(&dir, &args)
+    };
+
+    // the default profile overrides the top level settings
+    let json = show(&["config", "show"]);
+    assert_eq!(json["profile"], "testnet");
+    assert_eq!(json["key"], "conf/testnet/keys/testnet.json");
+    assert_eq!(json["data"], "conf/testnet/data.bin");
+    assert_eq!(json["compression"], "none");
+    assert_eq!(json["network"], "testnet");
+    assert!(json.get("kind").is_none());
+
+    // and top level settings apply to a profile that does not override them
+    let json = show(&["--profile", "mainnet", "config", "show"]);
+    assert_eq!(json["profile"], "mainnet");
+    assert_eq!(json["key"], "conf/mainnet/public_key.json");
+    assert_eq!(json["manifest"], manifest.to_str().unwrap());
+    assert_eq!(json["compression"], "zstd");
+    assert_eq!(json["kind"], "binary_fuse");
+
+    // configured paths are the defaults of file arguments
+    fs::write(
+        conf.join("mainnet").join("public_key.json"),
+        serde_json::to_vec(&key_manifest(&[keypair()])).unwrap(),
+    )
+    .unwrap();
+    let json = show(&["--profile", "mainnet", "key", "info"]);
+    assert_eq!(json["required"], 1);
+    let (code, _) = run(
+        &dir,
+        &[&config[..], &["--profile", "other", "config", "show"]].concat(),
+    );
+    assert_ne!(code, 0);
+
+    // a configuration file in the working directory resolves against it
+    fs::copy(
+        conf.join("xorf-generator.toml"),
+        dir.join("xorf-generator.toml"),
+    )
+    .unwrap();
+    let json = run_json(&dir, &["--profile", "mainnet", "config", "show"]);
+    assert_eq!(json["key"], "mainnet/public_key.json");
+    fs::remove_file(dir.join("xorf-generator.toml")).unwrap();
+    let (code, _) = run(&dir, &["--profile", "mainnet", "config", "show"]);
+    assert_ne!(code, 0);
+
+    let _ = fs::remove_dir_all(&dir);
+}
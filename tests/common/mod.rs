@@ -0,0 +1,61 @@
+//! Fixtures shared by the integration tests
+#![allow(dead_code)]
+use helium_crypto::{
+    multihash, multisig, KeyTag, KeyType, Keypair, Network, PublicKey, PublicKeyBinary, Sign,
+};
+
+/// A new random mainnet ed25519 keypair
+pub fn keypair() -> Keypair {
+    Keypair::generate(
+        KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        },
+        &mut rand::rngs::OsRng,
+    )
+}
+
+pub fn binary(keypair: &Keypair) -> PublicKeyBinary {
+    PublicKeyBinary::from(keypair.public_key().to_vec())
+}
+
+pub fn public_keys(keypairs: &[Keypair]) -> Vec<PublicKey> {
+    keypairs
+        .iter()
+        .map(|keypair| keypair.public_key().clone())
+        .collect()
+}
+
+/// The mainnet multisig key of the given keypairs requiring the given number
+/// of signatures
+pub fn multisig_key(keypairs: &[Keypair], required: u8) -> PublicKey {
+    multisig::PublicKey::generate(
+        Network::MainNet,
+        required,
+        multihash::Code::Sha2_256,
+        &public_keys(keypairs),
+    )
+    .unwrap()
+}
+
+/// A multisig signature over the given message by the first of the given
+/// keypairs
+pub fn multisig_sign(msg: &[u8], keypairs: &[Keypair], required: u8) -> Vec<u8> {
+    let signer = &keypairs[0];
+    let signatures = [(signer.public_key().clone(), signer.sign(msg).unwrap())];
+    multisig::Signature::new(
+        &multisig_key(keypairs, required),
+        &public_keys(keypairs),
+        &signatures,
+    )
+    .unwrap()
+    .to_vec()
+}
+
+/// Appends a member signature entry with the given member index to a
+/// multisig signature
+pub fn push_entry(signature: &mut Vec<u8>, index: u8, key_signature: &[u8]) {
+    signature.push(index);
+    signature.push(key_signature.len() as u8);
+    signature.extend_from_slice(key_signature);
+}
@@ -0,0 +1,397 @@
+use helium_crypto::PublicKeyBinary;
+use xorf_generator::{
+    Descriptor, DescriptorBuilder, DescriptorCompression, DescriptorGraph, DescriptorStats,
+    PromotionRule, ReasonCodes, DESCRIPTOR_MAJOR_VERSION, DESCRIPTOR_MINOR_VERSION,
+};
+
+mod common;
+use common::{binary, keypair};
+
+fn key() -> PublicKeyBinary {
+    binary(&keypair())
+}
+
+/// A descriptor with a node and an edge whose target is replaced with the
+/// given key index
+fn descriptor(target: Option<u32>) -> Descriptor {
+    let csv = format!("{},,fraud,0\n{},{},gaming,0\n", key(), key(), key());
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    let (mut descriptor, _) = builder.build().unwrap();
+    if let Some(target) = target {
+        descriptor.edges.as_mut().unwrap().edges[0].target = target;
+    }
+    descriptor
+}
+
+fn encode(descriptor: &Descriptor, compression: DescriptorCompression) -> Vec<u8> {
+    let mut data = vec![];
+    descriptor.to_writer(&mut data, compression).unwrap();
+    data
+}
+
+const COMPRESSIONS: [DescriptorCompression; 3] = [
+    DescriptorCompression::None,
+    DescriptorCompression::Gzip(6),
+    DescriptorCompression::Zstd(3),
+];
+
+#[test]
+fn reader_rejects_edges_outside_keys() {
+    let descriptor = descriptor(None);
+    for compression in COMPRESSIONS {
+        let data = encode(&descriptor, compression);
+        let read = Descriptor::from_reader(data.as_slice()).unwrap();
+        let stats = DescriptorStats::new(&read, 10);
+        assert_eq!(stats.edges, 1);
+        assert_eq!(stats.edge_keys, 2);
+    }
+
+    for target in [2, u32::MAX] {
+        let descriptor = self::descriptor(Some(target));
+        for compression in COMPRESSIONS {
+            let data = encode(&descriptor, compression);
+            assert!(Descriptor::from_reader(data.as_slice()).is_err());
+            assert!(
+                Descriptor::from_reader_with_compression(data.as_slice(), compression).is_err()
+            );
+        }
+    }
+}
+
+#[test]
+fn reader_rejects_empty_and_headerless_uncompressed() {
+    let descriptor = descriptor(None);
+    for compression in COMPRESSIONS {
+        let data = encode(&descriptor, compression);
+        assert_eq!(
+            Descriptor::from_reader(data.as_slice()).unwrap(),
+            descriptor
+        );
+        assert!(Descriptor::from_reader_with_compression(&[][..], compression).is_err());
+    }
+    assert!(Descriptor::from_reader(&[][..]).is_err());
+    assert!(Descriptor::from_reader(&[0x0a][..]).is_err());
+
+    // Uncompressed descriptors without a header are only read on request
+    let mut headerless = descriptor.clone();
+    headerless.header = None;
+    let data = encode(&headerless, DescriptorCompression::None);
+    assert!(Descriptor::from_reader(data.as_slice()).is_err());
+    let read =
+        Descriptor::from_reader_with_compression(data.as_slice(), DescriptorCompression::None);
+    assert_eq!(read.unwrap(), headerless);
+
+    let data = encode(&headerless, DescriptorCompression::Gzip(6));
+    assert_eq!(
+        Descriptor::from_reader(data.as_slice()).unwrap(),
+        headerless
+    );
+}
+
+#[test]
+fn graph_of_read_descriptor() {
+    let data = encode(&descriptor(None), DescriptorCompression::default());
+    let descriptor = Descriptor::from_reader(data.as_slice()).unwrap();
+    let mut dot = vec![];
+    DescriptorGraph::new(&descriptor)
+        .write_dot(&mut dot)
+        .unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    assert_eq!(dot.matches(" -- ").count(), 1);
+}
+
+fn build(sources: &[(&str, String)], precedence: &[&str]) -> Descriptor {
+    let mut builder = DescriptorBuilder::default();
+    for (name, csv) in sources {
+        builder.add_reader(name, csv.as_bytes()).unwrap();
+    }
+    builder.precedence(precedence);
+    builder.build().unwrap().0
+}
+
+#[test]
+fn edges_are_pruned_only_after_their_node() {
+    let (a, b, c, d) = (key(), key(), key(), key());
+    let csv = format!("{a},{b},gaming,0\n{a},,fraud,0\n{a},{c},gaming,0\n{c},{d},gaming,0\n");
+    let descriptor = build(&[("", csv)], &[]);
+    assert_eq!(descriptor.nodes.len(), 1);
+    assert!(descriptor.find_node(&a).is_some());
+    // the edge listed before the node of a is kept, the one after it is not
+    assert_eq!(descriptor.find_edges(&a).len(), 1);
+    assert_eq!(descriptor.find_edges(&b).len(), 1);
+    assert_eq!(descriptor.find_edges(&c).len(), 1);
+    assert_eq!(descriptor.find_edges(&d).len(), 1);
+    assert_eq!(descriptor.edges.unwrap().keys.len(), 4);
+}
+
+#[test]
+fn sources_are_read_in_order() {
+    let (a, b, c) = (key(), key(), key());
+    let first = format!("{a},{b},gaming,0\n");
+    let second = format!("{a},,fraud,0\n{a},{c},gaming,0\n");
+    let descriptor = build(&[("first", first), ("second", second)], &[]);
+    let node = descriptor.find_node(&a).unwrap();
+    assert_eq!(node.provenance.as_deref(), Some("second"));
+    assert_eq!(descriptor.find_edges(&a).len(), 1);
+    assert!(descriptor.find_edges(&c).is_empty());
+    let edges = descriptor.edges.unwrap().edges;
+    assert_eq!(edges[0].provenance, "first");
+}
+
+#[test]
+fn precedence_resolves_conflicts() {
+    let (a, b) = (key(), key());
+    let fraud = format!("{a},,fraud,0\n{b},,fraud,0\n");
+    let manual = format!("{a},,manual,2\n{b},,fraud,0\n");
+    let sources = [("fraud", fraud), ("manual", manual)];
+
+    let descriptor = build(&sources, &[]);
+    let node = descriptor.find_node(&a).unwrap();
+    assert_eq!(node.provenance.as_deref(), Some("fraud"));
+    assert_eq!(node.reason.as_deref(), Some("fraud"));
+
+    let mut builder = DescriptorBuilder::default();
+    for (name, csv) in &sources {
+        builder.add_reader(name, csv.as_bytes()).unwrap();
+    }
+    builder.precedence(&["manual"]);
+    let (descriptor, report) = builder.build().unwrap();
+    let node = descriptor.find_node(&a).unwrap();
+    assert_eq!(node.provenance.as_deref(), Some("manual"));
+    assert_eq!(node.reason.as_deref(), Some("manual"));
+    assert_eq!(node.carryover, 2);
+    // sources that agree on b are not a conflict
+    assert_eq!(report.conflicts.len(), 1);
+    let conflict = &report.conflicts[0];
+    assert_eq!(conflict.key, a);
+    assert_eq!(conflict.kept.source, "manual");
+    assert_eq!(conflict.overridden.len(), 1);
+    assert_eq!(conflict.overridden[0].source, "fraud");
+    assert_eq!(conflict.overridden[0].reason.as_deref(), Some("fraud"));
+}
+
+#[test]
+fn builder_rejects_unknown_and_duplicate_sources() {
+    let csv = format!("{},,fraud,0\n", key());
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("fraud", csv.as_bytes()).unwrap();
+    assert!(builder.add_reader("fraud", csv.as_bytes()).is_err());
+    builder.precedence(&["manual"]);
+    assert!(builder.build().is_err());
+}
+
+#[test]
+fn removals_remove_keys_and_edges() {
+    let (a, b, c, d, e, f) = (key(), key(), key(), key(), key(), key());
+    let fraud = format!("{a},,fraud,0\n{b},{c},gaming,0\n");
+    let manual = format!("{c},{d},gaming,0\n{d},{e},gaming,0\n{b},{e},gaming,0\n");
+    let removals = format!("{a},,appeal 1\n{c},,appeal 2\n{e},{d},wrongly linked\n{f},,appeal 3\n");
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("fraud", fraud.as_bytes()).unwrap();
+    builder.add_reader("manual", manual.as_bytes()).unwrap();
+    builder.add_removals_reader(removals.as_bytes()).unwrap();
+    let (descriptor, report) = builder.build().unwrap();
+
+    assert!(descriptor.nodes.is_empty());
+    assert!(descriptor.find_node(&a).is_none());
+    assert!(descriptor.find_edges(&c).is_empty());
+    assert_eq!(descriptor.find_edges(&d).len(), 0);
+    assert_eq!(descriptor.find_edges(&b).len(), 1);
+    assert_eq!(descriptor.find_edges(&e).len(), 1);
+
+    let removals = report
+        .removals
+        .iter()
+        .map(|removal| {
+            (
+                removal.reason.as_str(),
+                removal.sources.clone(),
+                removal.nodes,
+                removal.edges,
+            )
+        })
+        .collect::<Vec<_>>();
+    let sources = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    assert_eq!(
+        removals,
+        vec![
+            ("appeal 1", sources(&["fraud"]), 1, 0),
+            ("appeal 2", sources(&["fraud", "manual"]), 0, 2),
+            ("wrongly linked", sources(&["manual"]), 0, 1),
+            ("appeal 3", sources(&[]), 0, 0),
+        ]
+    );
+
+    // every removal needs a reason
+    let mut builder = DescriptorBuilder::default();
+    let removals = format!("{a},,appeal 1\n{b},, \n");
+    assert!(builder.add_removals_reader(removals.as_bytes()).is_err());
+}
+
+const REASON_CODES: &str = r#"{
+    "fraud": { "id": 1, "description": "Fraudulent location assertions" },
+    "gaming": { "id": 2 }
+}"#;
+
+#[test]
+fn reason_codes_are_stored_as_ids() {
+    for invalid in [
+        r#"{ "fraud": { "id": 0 } }"#,
+        r#"{ "fraud": { "id": 1 }, "gaming": { "id": 1 } }"#,
+    ] {
+        assert!(ReasonCodes::from_reader(invalid.as_bytes()).is_err());
+    }
+    let codes = ReasonCodes::from_reader(REASON_CODES.as_bytes()).unwrap();
+    assert_eq!(codes.id("gaming"), Some(2));
+    assert_eq!(codes.code(1), Some("fraud"));
+
+    let (a, b, c, d) = (key(), key(), key(), key());
+    let csv = format!("{a},,fraud,0\n{b},{c},gaming,0\n{d},,,0\n");
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    builder.reason_codes(codes);
+    let (descriptor, _) = builder.build().unwrap();
+    let data = encode(&descriptor, DescriptorCompression::default());
+    let descriptor = Descriptor::from_reader(data.as_slice()).unwrap();
+
+    let node = descriptor
+        .nodes
+        .iter()
+        .find(|node| node.key == a.as_ref())
+        .unwrap();
+    assert!(node.reason.is_empty());
+    assert_eq!(node.reason_code, 1);
+    assert_eq!(descriptor.reason(&node.reason, node.reason_code), "fraud");
+    let edge = &descriptor.edges.as_ref().unwrap().edges[0];
+    assert_eq!(edge.reason_code, 2);
+    assert_eq!(descriptor.reason_codes().code(2), Some("gaming"));
+    assert_eq!(descriptor.reason("", 7), "#7");
+
+    let counts = descriptor.reason_counts();
+    assert_eq!(counts["fraud"].nodes, 1);
+    assert_eq!(counts["gaming"].edges, 1);
+    assert_eq!(counts["unspecified"].nodes, 1);
+}
+
+#[test]
+fn reason_codes_must_be_known() {
+    let codes = ReasonCodes::from_reader(REASON_CODES.as_bytes()).unwrap();
+    let (a, b) = (key(), key());
+
+    let csv = format!("{a},,fraud,0\n{a},{b},collusion,0\n");
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    builder.reason_codes(codes.clone());
+    assert!(builder.build().is_err());
+
+    // as must the reason for promoted keys
+    let csv = format!("{a},{b},gaming,0\n");
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    builder.reason_codes(codes);
+    builder.promotion(PromotionRule::default());
+    assert!(builder.build().is_err());
+
+    // free text reasons are stored as text without a registry
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    let (descriptor, _) = builder.build().unwrap();
+    let edge = &descriptor.edges.as_ref().unwrap().edges[0];
+    assert_eq!((edge.reason.as_str(), edge.reason_code), ("gaming", 0));
+    assert!(descriptor.reasons.is_empty());
+}
+
+#[test]
+fn promotion_thresholds() {
+    let (hub, x, y, z) = (key(), key(), key(), key());
+    let csv =
+        format!("{hub},{x},gaming,3\n{hub},{y},gaming,1\n{z},{hub},gaming,2\n{x},{y},gaming,0\n");
+    let peers = format!("{hub},6\n{x},0\n");
+    let build = |max_edges, min_fraction| {
+        let mut rule = PromotionRule {
+            max_edges,
+            min_fraction,
+            ..Default::default()
+        };
+        rule.read_observed_peers_reader(peers.as_bytes()).unwrap();
+        let mut builder = DescriptorBuilder::default();
+        builder.add_reader("", csv.as_bytes()).unwrap();
+        builder.promotion(rule);
+        builder.build().unwrap()
+    };
+
+    // more than max_edges edges, or at least min_fraction of the observed
+    // peers, promotes a key
+    for (max_edges, min_fraction) in [(Some(2), None), (None, Some(0.5))] {
+        let (descriptor, report) = build(max_edges, min_fraction);
+        assert_eq!(report.promotions.len(), 1);
+        let promotion = &report.promotions[0];
+        assert_eq!(promotion.key, hub);
+        assert_eq!(promotion.edges, 3);
+        assert_eq!(promotion.observed_peers, Some(6));
+
+        let node = descriptor.find_node(&hub).unwrap();
+        assert_eq!(node.reason.as_deref(), Some("promoted"));
+        assert_eq!(node.provenance.as_deref(), Some("promotion"));
+        // the lowest carryover of the pruned edges
+        assert_eq!(node.carryover, 1);
+        assert!(descriptor.find_edges(&hub).is_empty());
+        assert!(descriptor.find_edges(&z).is_empty());
+        assert_eq!(descriptor.find_edges(&x).len(), 1);
+    }
+
+    // exactly max_edges edges or a smaller fraction does not
+    for (max_edges, min_fraction) in [(Some(3), None), (None, Some(0.6))] {
+        let (descriptor, report) = build(max_edges, min_fraction);
+        assert!(
+            report.promotions.is_empty(),
+            "{max_edges:?} {min_fraction:?}"
+        );
+        assert_eq!(descriptor.find_edges(&hub).len(), 3);
+    }
+
+    // and keys without observed peers are not promoted by fraction
+    let (_, report) = build(None, Some(0.0));
+    assert_eq!(report.promotions.len(), 1);
+    assert_eq!(report.promotions[0].key, hub);
+}
+
+#[test]
+fn reader_refuses_newer_major_versions() {
+    let mut builder = DescriptorBuilder::default();
+    builder
+        .add_reader("", format!("{},,fraud,0\n", key()).as_bytes())
+        .unwrap();
+    builder.serial(9);
+    let (descriptor, _) = builder.build().unwrap();
+    let header = descriptor.header.clone().unwrap();
+    assert_eq!(
+        descriptor.version(),
+        (DESCRIPTOR_MAJOR_VERSION, DESCRIPTOR_MINOR_VERSION)
+    );
+    assert_eq!(header.serial, 9);
+    assert_eq!(header.generator_version, env!("CARGO_PKG_VERSION"));
+
+    let with_version = |major, minor| {
+        let mut descriptor = descriptor.clone();
+        let header = descriptor.header.as_mut().unwrap();
+        header.major_version = major;
+        header.minor_version = minor;
+        descriptor
+    };
+    for compression in COMPRESSIONS {
+        // a newer minor version only adds fields older readers can skip
+        let newer_minor = with_version(DESCRIPTOR_MAJOR_VERSION, DESCRIPTOR_MINOR_VERSION + 1);
+        let data = encode(&newer_minor, compression);
+        let read = Descriptor::from_reader(data.as_slice()).unwrap();
+        assert_eq!(read.version(), newer_minor.version());
+
+        let newer_major = with_version(DESCRIPTOR_MAJOR_VERSION + 1, 0);
+        assert!(newer_major.check_version().is_err());
+        let data = encode(&newer_major, compression);
+        let err = Descriptor::from_reader(data.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("newer than supported"), "{err}");
+        assert!(Descriptor::from_reader_with_compression(data.as_slice(), compression).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use helium_crypto::{multihash, multisig, Network, PublicKey, Sign};
+use xorf_generator::{DescriptorBuilder, Filter, FilterKind, FILTTER_VERSION};
+
+mod common;
+use common::{binary, keypair};
+
+#[test]
+fn binary_fuse_generate_sign_verify() {
+    let hotspots = (0..100).map(|_| keypair()).collect::<Vec<_>>();
+    let mut csv = String::new();
+    for hotspot in &hotspots[..90] {
+        csv.push_str(&format!("{},,fraud,0\n", binary(hotspot)));
+    }
+    for pair in hotspots[90..].chunks(2) {
+        csv.push_str(&format!(
+            "{},{},gaming,0\n",
+            binary(&pair[0]),
+            binary(&pair[1])
+        ));
+    }
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    let (descriptor, _) = builder.build().unwrap();
+
+    // The signing data round trips as a binary fuse filter
+    let generated =
+        Filter::from_descriptor_with_kind(7, &descriptor, FilterKind::BinaryFuse).unwrap();
+    let data = generated.to_signing_bytes().unwrap();
+    let mut filter = Filter::from_signing_bytes(&data, FILTTER_VERSION).unwrap();
+    assert_eq!(filter.filter.kind(), "binary_fuse");
+    assert_eq!(filter.serial, 7);
+
+    let members = [keypair(), keypair()];
+    let public_keys = members
+        .iter()
+        .map(|member| member.public_key().clone())
+        .collect::<Vec<PublicKey>>();
+    let key =
+        multisig::PublicKey::generate(Network::MainNet, 1, multihash::Code::Sha2_256, &public_keys)
+            .unwrap();
+    let msg = filter.signed_message().unwrap();
+    let signatures = [(
+        members[0].public_key().clone(),
+        members[0].sign(&msg).unwrap(),
+    )];
+    filter.signature = multisig::Signature::new(&key, &public_keys, &signatures)
+        .unwrap()
+        .to_vec();
+
+    let filter = Filter::from_bytes(&filter.to_bytes().unwrap()).unwrap();
+    filter.verify(&key).unwrap();
+    assert_eq!(filter.filter.kind(), "binary_fuse");
+    for hotspot in &hotspots[..90] {
+        assert!(filter.contains(&binary(hotspot)));
+    }
+    for pair in hotspots[90..].chunks(2) {
+        assert!(filter.contains_edge(&binary(&pair[0]), &binary(&pair[1])));
+    }
+}
@@ -0,0 +1,146 @@
+use helium_crypto::{Keypair, PublicKeyBinary, Sign};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::net::TcpListener;
+use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+use xorf_generator::{
+    public_key_hash, xorf::Xor32, Filter, FilterClient, FilterService, FilterWatcher,
+};
+
+mod common;
+use common::keypair;
+
+fn write_filter(dir: &Path, name: &str, keypair: &Keypair, serial: u32, keys: &[PublicKeyBinary]) {
+    let hashes = keys.iter().map(public_key_hash).collect::<Vec<_>>();
+    write_hashes(dir, name, keypair, serial, &hashes);
+}
+
+fn write_hashes(dir: &Path, name: &str, keypair: &Keypair, serial: u32, hashes: &[u64]) {
+    let mut filter = Filter::new(serial, Xor32::from(hashes)).unwrap();
+    filter.signature = keypair.sign(&filter.signed_message().unwrap()).unwrap();
+    // Write and rename so the watcher never sees a partial file
+    let tmp = dir.join(format!("{name}.tmp"));
+    fs::write(&tmp, filter.to_bytes().unwrap()).unwrap();
+    fs::rename(&tmp, dir.join(name)).unwrap();
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("xorf-generator-grpc-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Serves the filters in the given directory and connects a client to them
+async fn serve(dir: &Path, signer: &Keypair) -> FilterClient {
+    let watcher = FilterWatcher::builder(dir, signer.public_key().clone())
+        .interval(Duration::from_millis(50))
+        .start()
+        .unwrap();
+    let service = FilterService::new(Arc::new(watcher))
+        .interval(Duration::from_millis(50))
+        .into_server();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    FilterClient::connect(format!("http://{addr}"), signer.public_key().clone())
+        .await
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn filter_service() {
+    let dir = temp_dir("service");
+
+    let signer = keypair();
+    let members = (0..3)
+        .map(|_| PublicKeyBinary::from(keypair().public_key().to_vec()))
+        .collect::<Vec<_>>();
+    let outsider = PublicKeyBinary::from(keypair().public_key().to_vec());
+    write_filter(&dir, "1.bin", &signer, 1, &members[..2]);
+
+    let mut client = serve(&dir, &signer).await;
+
+    let latest = client.latest_filter(0).await.unwrap().unwrap();
+    assert_eq!(latest.serial, 1);
+    assert!(latest.contains(&members[0]));
+    assert!(client.latest_filter(1).await.unwrap().is_none());
+
+    let membership = client
+        .check_membership(&[
+            (members[0].clone(), None),
+            (members[2].clone(), None),
+            (outsider.clone(), None),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(membership.serial, 1);
+    assert_eq!(membership.in_filter, vec![true, false, false]);
+
+    let mut updates = Box::pin(client.filter_updates(0).await.unwrap());
+    let timeout = Duration::from_secs(10);
+    let first = tokio::time::timeout(timeout, updates.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(first.serial, 1);
+
+    // A newer filter signed by a different key is never served
+    write_filter(&dir, "3.bin", &keypair(), 3, &members);
+    write_filter(&dir, "2.bin", &signer, 2, &members);
+    let second = tokio::time::timeout(timeout, updates.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(second.serial, 2);
+    assert!(second.contains(&members[2]));
+
+    let membership = client
+        .check_membership(&[(members[2].clone(), None)])
+        .await
+        .unwrap();
+    assert_eq!(membership.serial, 2);
+    assert_eq!(membership.in_filter, vec![true]);
+    assert_eq!(client.latest_filter(0).await.unwrap().unwrap().serial, 2);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn large_filter() {
+    let dir = temp_dir("large");
+    let signer = keypair();
+    let member = PublicKeyBinary::from(keypair().public_key().to_vec());
+    let mut hashes = (1..=1_000_000u64)
+        .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+        .collect::<Vec<_>>();
+    hashes.push(public_key_hash(&member));
+    write_hashes(&dir, "1.bin", &signer, 1, &hashes);
+    assert!(fs::metadata(dir.join("1.bin")).unwrap().len() > 4 * 1024 * 1024);
+
+    let mut client = serve(&dir, &signer).await;
+    let latest = client.latest_filter(0).await.unwrap().unwrap();
+    assert_eq!(latest.serial, 1);
+    assert!(latest.contains(&member));
+
+    let mut updates = Box::pin(client.filter_updates(0).await.unwrap());
+    let update = tokio::time::timeout(Duration::from_secs(30), updates.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(update.serial, 1);
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,118 @@
+use helium_crypto::{Keypair, Sign};
+use sha2::{Digest, Sha256};
+use xorf_generator::{
+    base64_serde, xorf::Xor32, Attestation, Filter, Manifest, PublicKeyManifest,
+    FILTER_ENVELOPE_VERSION,
+};
+
+mod common;
+use common::keypair;
+
+fn key_manifest(keypairs: &[Keypair]) -> PublicKeyManifest {
+    PublicKeyManifest {
+        public_keys: keypairs
+            .iter()
+            .map(|keypair| keypair.public_key().clone().into())
+            .collect(),
+        required: 1,
+        policy: vec![],
+    }
+}
+
+fn attestation(signer: &Keypair, data_hash: &[u8]) -> Attestation {
+    let mut attestation = Attestation {
+        timestamp: 1_700_000_000,
+        descriptor_hash: base64_serde::encode(&Sha256::digest(b"descriptor")),
+        diff_hash: None,
+        comment: Some("reviewed".to_string()),
+        signature: vec![],
+    };
+    let msg = attestation.to_signing_bytes(data_hash).unwrap();
+    attestation.signature = signer.sign(&msg).unwrap();
+    attestation
+}
+
+/// Adds the signature of the given signer over the given message, and the
+/// given attestation, to the manifest
+fn sign(
+    manifest: &Manifest,
+    signer: &Keypair,
+    msg: &[u8],
+    attestation: Option<Attestation>,
+) -> Manifest {
+    let mut json = serde_json::to_value(manifest).unwrap();
+    let address = signer.public_key().to_string();
+    for signature in json["signatures"].as_array_mut().unwrap() {
+        if signature["address"] == address.as_str() {
+            signature["signature"] = base64_serde::encode(&signer.sign(msg).unwrap()).into();
+            if let Some(attestation) = &attestation {
+                signature["attestation"] = serde_json::to_value(attestation).unwrap();
+            }
+        }
+    }
+    serde_json::from_value(json).unwrap()
+}
+
+#[test]
+fn failed_attestation_does_not_count() {
+    let members = [keypair(), keypair()];
+    let key_manifest = key_manifest(&members);
+    let filter = Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+    let manifest = Manifest::for_filter(&filter, &key_manifest).unwrap();
+    let msg = filter.signed_message().unwrap();
+
+    let attested = sign(
+        &manifest,
+        &members[0],
+        &msg,
+        Some(attestation(&members[0], &filter.hash().unwrap())),
+    );
+    let signers = attested.signers(&key_manifest, &msg);
+    assert_eq!(signers, vec![members[0].public_key().clone()]);
+    assert!(key_manifest.evaluate_policy(&signers).satisfied);
+
+    // An attestation for different signing data does not verify, so the
+    // signature does not count even though the signature itself verifies
+    let misattested = sign(
+        &manifest,
+        &members[0],
+        &msg,
+        Some(attestation(&members[0], &Sha256::digest(b"other"))),
+    );
+    let verify = misattested.verify_signatures(&msg);
+    assert!(!verify[0].is_verified());
+    let signers = misattested.signers(&key_manifest, &msg);
+    assert!(signers.is_empty());
+    assert!(!key_manifest.evaluate_policy(&signers).satisfied);
+}
+
+#[test]
+fn envelope_manifest_attestation_verifies() {
+    let members = [keypair(), keypair()];
+    let key_manifest = key_manifest(&members);
+    let data = Filter::new(1, Xor32::from(&[1u64, 2, 3][..]))
+        .unwrap()
+        .to_signing_bytes()
+        .unwrap();
+    let filter = Filter::from_signing_bytes(&data, FILTER_ENVELOPE_VERSION).unwrap();
+    let manifest = Manifest::for_filter(&filter, &key_manifest).unwrap();
+    assert_eq!(manifest.version, FILTER_ENVELOPE_VERSION);
+
+    // Members sign the envelope and attest to its hash
+    let msg = filter.signed_message().unwrap();
+    let manifest = sign(
+        &manifest,
+        &members[0],
+        &msg,
+        Some(attestation(
+            &members[0],
+            &filter.signed_message_hash().unwrap(),
+        )),
+    );
+    let verify = manifest.verify_signatures(&msg);
+    assert!(verify[0].is_verified());
+    assert_eq!(
+        manifest.signers(&key_manifest, &msg),
+        vec![members[0].public_key().clone()]
+    );
+}
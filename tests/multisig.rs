@@ -0,0 +1,78 @@
+use helium_crypto::{Keypair, Sign};
+use xorf_generator::{xorf::Xor32, Filter};
+
+mod common;
+use common::{keypair, multisig_key, multisig_sign, push_entry};
+
+/// A multisig signature over the filter by the first of the given keypairs
+fn sign(filter: &Filter, keypairs: &[Keypair], required: u8) -> Vec<u8> {
+    multisig_sign(&filter.signed_message().unwrap(), keypairs, required)
+}
+
+#[test]
+fn multisig_members_must_match_key() {
+    let members = [keypair(), keypair()];
+    let key = multisig_key(&members, 1);
+    let mut filter = Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+
+    filter.signature = sign(&filter, &members, 1);
+    assert!(filter.verify(&key).is_ok());
+
+    // A signature by a different set of keys with the same threshold carries
+    // those keys, and verifies against them unless they are checked against
+    // the key digest
+    let others = [keypair(), keypair()];
+    filter.signature = sign(&filter, &others, 1);
+    assert!(filter.verify(&key).is_err());
+}
+
+#[test]
+fn multisig_members_count_once() {
+    let members = [keypair(), keypair(), keypair()];
+    let key = multisig_key(&members, 2);
+    let mut filter = Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+    let signature = sign(&filter, &members, 2);
+    filter.signature = signature.clone();
+    assert!(filter.verify(&key).is_err());
+
+    // Repeating the one member signature does not meet the threshold of two
+    let entry = &signature[members.len() * 33..];
+    filter.signature.extend_from_slice(entry);
+    assert!(filter.verify(&key).is_err());
+
+    // A second member does
+    let msg = filter.signed_message().unwrap();
+    let index = signature[..members.len() * 33]
+        .chunks(33)
+        .position(|key| key == members[1].public_key().to_vec())
+        .unwrap();
+    filter.signature = signature;
+    push_entry(
+        &mut filter.signature,
+        index as u8,
+        &members[1].sign(&msg).unwrap(),
+    );
+    assert!(filter.verify(&key).is_ok());
+}
+
+#[test]
+fn multisig_member_index_out_of_range() {
+    let members = [keypair(), keypair()];
+    let mut filter = Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+    let msg = filter.signed_message().unwrap();
+    let key_signature = members[0].sign(&msg).unwrap();
+
+    for required in [1, 2] {
+        let key = multisig_key(&members, required);
+        filter.signature = sign(&filter, &members, required);
+        for index in [members.len() as u8, u8::MAX] {
+            let mut signature = filter.signature.clone();
+            push_entry(&mut signature, index, &key_signature);
+            let filter = Filter {
+                signature,
+                ..Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap()
+            };
+            assert_eq!(filter.verify(&key).is_ok(), required == 1);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Signs with keys in a SoftHSM token. The token is expected to be initialized
+//! with:
+//!
+//! ```shell
+//! softhsm2-util --init-token --free --label xorf-test --pin 1234 --so-pin 5678
+//! ```
+//!
+//! and the SoftHSM module is read from `XORF_PKCS11_MODULE`, defaulting to
+//! `/usr/lib/softhsm/libsofthsm2.so`.
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    mechanism::Mechanism,
+    object::Attribute,
+    session::UserType,
+    types::AuthPin,
+};
+use helium_crypto::{KeyType, Network, Verify};
+use xorf_generator::Pkcs11Signer;
+
+const TOKEN: &str = "xorf-test";
+const PIN: &str = "1234";
+
+/// DER encoded object identifiers of the P-256 and Ed25519 curves
+const P256_PARAMS: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const ED25519_PARAMS: &[u8] = &[0x06, 0x03, 0x2b, 0x65, 0x70];
+
+fn module() -> String {
+    std::env::var("XORF_PKCS11_MODULE")
+        .unwrap_or_else(|_| "/usr/lib/softhsm/libsofthsm2.so".to_string())
+}
+
+/// Generates key pairs with the given labels in the test token. The module is
+/// finalized before returning so the signer can initialize it again.
+fn generate_keys(mechanism: Mechanism, params: &[u8], labels: &[String]) {
+    let pkcs11 = Pkcs11::new(module()).unwrap();
+    pkcs11.initialize(CInitializeArgs::OsThreads).unwrap();
+    let slot = pkcs11
+        .get_slots_with_token()
+        .unwrap()
+        .into_iter()
+        .find(|slot| pkcs11.get_token_info(*slot).unwrap().label() == TOKEN)
+        .expect("initialized test token");
+    let session = pkcs11.open_rw_session(slot).unwrap();
+    session
+        .login(UserType::User, Some(&AuthPin::new(PIN.into())))
+        .unwrap();
+    for label in labels {
+        let label = label.as_bytes().to_vec();
+        let public = [
+            Attribute::Token(true),
+            Attribute::Label(label.clone()),
+            Attribute::EcParams(params.to_vec()),
+            Attribute::Verify(true),
+        ];
+        let private = [
+            Attribute::Token(true),
+            Attribute::Label(label),
+            Attribute::Private(true),
+            Attribute::Sensitive(true),
+            Attribute::Sign(true),
+        ];
+        session
+            .generate_key_pair(&mechanism, &public, &private)
+            .unwrap();
+    }
+}
+
+fn open(label: &str, network: Network) -> xorf_generator::Result<Pkcs11Signer> {
+    Pkcs11Signer::open(module(), TOKEN, PIN, label, network)
+}
+
+#[test]
+#[ignore = "requires an initialized SoftHSM token"]
+fn softhsm_sign() {
+    let suffix = std::process::id();
+    let ed25519 = format!("ed25519-{suffix}");
+    // about half of all P-256 keys are ecc compact
+    let p256 = (0..16)
+        .map(|i| format!("p256-{suffix}-{i}"))
+        .collect::<Vec<_>>();
+    generate_keys(
+        Mechanism::EccEdwardsKeyPairGen,
+        ED25519_PARAMS,
+        std::slice::from_ref(&ed25519),
+    );
+    generate_keys(Mechanism::EccKeyPairGen, P256_PARAMS, &p256);
+
+    let msg = b"signing data";
+    for network in [Network::MainNet, Network::TestNet] {
+        let signer = open(&ed25519, network).unwrap();
+        assert_eq!(signer.public_key().key_type(), KeyType::Ed25519);
+        assert_eq!(signer.public_key().network, network);
+        let signature = signer.sign(msg).unwrap();
+        assert!(signer.public_key().verify(msg, &signature).is_ok());
+        drop(signer);
+
+        let signer = p256
+            .iter()
+            .find_map(|label| open(label, network).ok())
+            .expect("an ecc compact key");
+        assert_eq!(signer.public_key().key_type(), KeyType::EccCompact);
+        assert_eq!(signer.public_key().network, network);
+        let signature = signer.sign(msg).unwrap();
+        assert!(signer.public_key().verify(msg, &signature).is_ok());
+    }
+
+    assert!(open("missing", Network::MainNet).is_err());
+}
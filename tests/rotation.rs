@@ -0,0 +1,159 @@
+use helium_crypto::{Keypair, Sign};
+use xorf::Xor32;
+use xorf_generator::{
+    base64_serde, Filter, KeyRotation, PolicyClause, PolicyMember, PublicKeyManifest,
+};
+
+mod common;
+use common::{keypair, multisig_sign};
+
+fn key_manifest(keypairs: &[Keypair]) -> PublicKeyManifest {
+    PublicKeyManifest {
+        public_keys: keypairs
+            .iter()
+            .map(|keypair| keypair.public_key().clone().into())
+            .collect(),
+        required: 1,
+        policy: vec![],
+    }
+}
+
+fn clause(name: &str, keypair: &Keypair) -> PolicyClause {
+    PolicyClause {
+        name: name.to_string(),
+        threshold: 1,
+        members: vec![PolicyMember::Key(keypair.public_key().clone().into())],
+    }
+}
+
+/// Signs the rotation by the given members of its current key
+fn sign(rotation: KeyRotation, signers: &[Keypair]) -> KeyRotation {
+    let msg = rotation.to_signing_bytes().unwrap();
+    let mut json = serde_json::to_value(&rotation).unwrap();
+    for signature in json["signatures"].as_array_mut().unwrap() {
+        let address = signature["address"].as_str().unwrap().to_string();
+        if let Some(signer) = signers
+            .iter()
+            .find(|signer| signer.public_key().to_string() == address)
+        {
+            signature["signature"] = base64_serde::encode(&signer.sign(&msg).unwrap()).into();
+        }
+    }
+    KeyRotation::from_reader(json.to_string().as_bytes()).unwrap()
+}
+
+fn round_trip(rotation: &KeyRotation) -> KeyRotation {
+    KeyRotation::from_reader(serde_json::to_vec(rotation).unwrap().as_slice()).unwrap()
+}
+
+#[test]
+fn rotation_policy_is_signed() {
+    let members = [keypair(), keypair()];
+    let new_members = [keypair(), keypair()];
+    let mut from = key_manifest(&members);
+    from.policy = vec![clause("ops", &members[0])];
+    let mut to = key_manifest(&new_members);
+    to.policy = vec![clause("ops", &new_members[0])];
+    let current = from.public_key().unwrap();
+
+    let rotation = sign(KeyRotation::new(from, to), &members[..1]);
+    rotation.verify(&current).unwrap();
+
+    // Changing the policy of the new key does not change the new multisig key
+    // but invalidates the signatures
+    let mut changed = round_trip(&rotation);
+    changed.to.policy = vec![clause("ops", &new_members[1])];
+    assert_eq!(
+        changed.to.public_key().unwrap(),
+        rotation.to.public_key().unwrap()
+    );
+    assert!(changed.verify(&current).is_err());
+
+    let mut changed = round_trip(&rotation);
+    changed.to.policy.clear();
+    assert!(changed.verify(&current).is_err());
+
+    let mut changed = round_trip(&rotation);
+    changed.to.policy[0] = PolicyClause {
+        name: "ops".to_string(),
+        threshold: 2,
+        members: vec![PolicyMember::Weighted {
+            address: new_members[0].public_key().clone().into(),
+            weight: 2,
+        }],
+    };
+    assert!(changed.verify(&current).is_err());
+
+    // as does changing the policy of the current key
+    let mut changed = round_trip(&rotation);
+    changed.from.policy[0].name = "dev".to_string();
+    assert!(changed.verify(&current).is_err());
+}
+
+#[test]
+fn rotation_policy_is_validated() {
+    let members = [keypair(), keypair()];
+    let mut to = key_manifest(&[keypair()]);
+    // a clause member that is not one of the new public keys
+    to.policy = vec![clause("ops", &members[0])];
+    let rotation = KeyRotation::new(key_manifest(&members), to);
+    let json = serde_json::to_vec(&rotation).unwrap();
+    assert!(KeyRotation::from_reader(json.as_slice()).is_err());
+}
+
+#[test]
+fn clause_members_are_validated() {
+    let members = [keypair(), keypair()];
+    let weighted = |keypair: &Keypair, weight| PolicyMember::Weighted {
+        address: keypair.public_key().clone().into(),
+        weight,
+    };
+    let duplicate = PolicyClause {
+        name: "ops".to_string(),
+        threshold: 2,
+        members: vec![weighted(&members[0], 1), weighted(&members[0], 1)],
+    };
+    let overflow = PolicyClause {
+        name: "ops".to_string(),
+        threshold: 1,
+        members: vec![weighted(&members[0], u32::MAX), weighted(&members[1], 1)],
+    };
+    for clause in [duplicate, overflow] {
+        let mut manifest = key_manifest(&members);
+        manifest.policy = vec![clause];
+        let json = serde_json::to_vec(&manifest).unwrap();
+        assert!(PublicKeyManifest::from_reader(json.as_slice()).is_err());
+        assert!(manifest.validate_policy().is_err());
+    }
+
+    // an unvalidated clause still counts a member once
+    let clause = PolicyClause {
+        name: "ops".to_string(),
+        threshold: 2,
+        members: vec![weighted(&members[0], 1), weighted(&members[0], 1)],
+    };
+    let verify = clause.evaluate(&[members[0].public_key().clone()]);
+    assert_eq!(verify.weight, 1);
+    assert!(!verify.satisfied);
+}
+
+#[test]
+fn chain_enforces_final_policy() {
+    let members = [keypair(), keypair()];
+    let new_members = [keypair(), keypair()];
+    let from = key_manifest(&members);
+    let root = from.public_key().unwrap();
+
+    let mut filter = Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+    let msg = filter.signed_message().unwrap();
+    // signed by the first of the new members only
+    filter.signature = multisig_sign(&msg, &new_members, 1);
+
+    for (clause_member, satisfied) in [(&new_members[0], true), (&new_members[1], false)] {
+        let mut to = key_manifest(&new_members);
+        to.policy = vec![clause("ops", clause_member)];
+        let chain = [sign(KeyRotation::new(from.clone(), to), &members[..1])];
+        filter.verify(&chain[0].to.public_key().unwrap()).unwrap();
+        assert_eq!(filter.verify_with_chain(&root, &chain).is_ok(), satisfied);
+    }
+}
@@ -0,0 +1,242 @@
+use helium_crypto::{Keypair, Sign};
+use std::{fs, path::PathBuf};
+use xorf_generator::{
+    verify::{Error, FilterRef},
+    xorf::Xor32,
+    DescriptorBuilder, Filter, FilterKind, FilterView, PublicKeyManifest,
+};
+
+mod common;
+use common::{binary, keypair, multisig_key, multisig_sign, push_entry};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "xorf-generator-verify-{name}-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A signed filter of the given kind with the given hotspots and edges
+/// between consecutive pairs of the given edge hotspots
+fn signed_filter(
+    kind: FilterKind,
+    hotspots: &[Keypair],
+    edges: &[Keypair],
+    signer: &Keypair,
+) -> Filter {
+    let mut csv = String::new();
+    for hotspot in hotspots {
+        csv.push_str(&format!("{},,fraud,0\n", binary(hotspot)));
+    }
+    for pair in edges.chunks(2) {
+        csv.push_str(&format!(
+            "{},{},gaming,0\n",
+            binary(&pair[0]),
+            binary(&pair[1])
+        ));
+    }
+    let mut builder = DescriptorBuilder::default();
+    builder.add_reader("", csv.as_bytes()).unwrap();
+    let (descriptor, _) = builder.build().unwrap();
+    let mut filter = Filter::from_descriptor_with_kind(1, &descriptor, kind).unwrap();
+    filter.signature = signer.sign(&filter.signed_message().unwrap()).unwrap();
+    filter
+}
+
+#[test]
+fn filter_ref_matches_filter() {
+    let hotspots = (0..200).map(|_| keypair()).collect::<Vec<_>>();
+    let others = (0..200).map(|_| keypair()).collect::<Vec<_>>();
+    let signer = keypair();
+    let public_key = signer.public_key().to_vec();
+    let other_key = keypair().public_key().to_vec();
+
+    for kind in [FilterKind::Xor, FilterKind::BinaryFuse] {
+        let filter = signed_filter(kind, &hotspots[..180], &hotspots[180..], &signer);
+        let bytes = filter.to_bytes().unwrap();
+        let filter_ref = FilterRef::from_bytes(&bytes).unwrap();
+        assert_eq!(filter_ref.kind(), kind.to_string());
+        assert_eq!(filter_ref.serial(), filter.serial);
+        assert_eq!(filter_ref.hash().to_vec(), filter.hash().unwrap());
+
+        for hotspot in hotspots.iter().chain(&others) {
+            let key = binary(hotspot);
+            assert_eq!(filter_ref.contains(key.as_ref()), filter.contains(&key));
+        }
+        for pair in hotspots[180..].chunks(2).chain(others.chunks(2)) {
+            let (a, b) = (binary(&pair[0]), binary(&pair[1]));
+            assert_eq!(
+                filter_ref.contains_edge(a.as_ref(), b.as_ref()),
+                filter.contains_edge(&a, &b)
+            );
+        }
+
+        let signer_key = helium_crypto::PublicKey::from_bytes(&public_key).unwrap();
+        let wrong_key = helium_crypto::PublicKey::from_bytes(&other_key).unwrap();
+        assert!(filter.verify(&signer_key).is_ok());
+        assert!(filter_ref.verify(&public_key).is_ok());
+        assert!(filter.verify(&wrong_key).is_err());
+        assert!(filter_ref.verify(&other_key).is_err());
+    }
+}
+
+/// The bytes of an unsigned version 2 binary fuse filter with the given
+/// header and number of fingerprints
+fn bfuse_bytes(
+    segment_length: u32,
+    segment_length_mask: u32,
+    segment_count_length: u32,
+    fingerprints: u64,
+) -> Vec<u8> {
+    let mut bytes = vec![2, 0, 0];
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&7u64.to_le_bytes());
+    bytes.extend_from_slice(&segment_length.to_le_bytes());
+    bytes.extend_from_slice(&segment_length_mask.to_le_bytes());
+    bytes.extend_from_slice(&segment_count_length.to_le_bytes());
+    bytes.extend_from_slice(&fingerprints.to_le_bytes());
+    bytes.resize(bytes.len() + fingerprints as usize * 4, 0);
+    bytes
+}
+
+#[test]
+fn filter_ref_rejects_malformed_bfuse() {
+    let malformed = |bytes: &[u8]| matches!(FilterRef::from_bytes(bytes), Err(Error::Malformed(_)));
+
+    // a well formed header with an empty filter
+    let bytes = bfuse_bytes(4, 3, 8, 16);
+    let filter_ref = FilterRef::from_bytes(&bytes).unwrap();
+    assert!(!filter_ref.contains(&[0; 33]));
+
+    // segment count length not a multiple of the segment length, which would
+    // index past the fingerprints
+    assert!(malformed(&bfuse_bytes(4, 3, 5, 13)));
+    // fingerprints that do not match the segments
+    assert!(malformed(&bfuse_bytes(4, 3, 8, 15)));
+    assert!(malformed(&bfuse_bytes(4, 3, 8, 17)));
+    // invalid segment lengths and masks
+    assert!(malformed(&bfuse_bytes(3, 2, 6, 12)));
+    assert!(malformed(&bfuse_bytes(4, 1, 8, 16)));
+    assert!(malformed(&bfuse_bytes(0, u32::MAX, 0, 0)));
+    assert!(malformed(&bfuse_bytes(4, 3, 0, 8)));
+    // segments whose indices overflow a u32
+    assert!(malformed(&bfuse_bytes(1 << 31, u32::MAX >> 1, 1 << 31, 4)));
+
+    // every truncation of a well formed filter
+    for len in 0..bytes.len() {
+        assert!(FilterRef::from_bytes(&bytes[..len]).is_err());
+    }
+}
+
+#[test]
+fn filter_ref_rejects_truncated_filters() {
+    let hotspots = (0..20).map(|_| keypair()).collect::<Vec<_>>();
+    for kind in [FilterKind::Xor, FilterKind::BinaryFuse] {
+        let bytes = signed_filter(kind, &hotspots, &[], &keypair())
+            .to_bytes()
+            .unwrap();
+        for len in 0..bytes.len() {
+            assert!(FilterRef::from_bytes(&bytes[..len]).is_err());
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(FilterRef::from_bytes(&trailing).is_err());
+    }
+}
+
+#[test]
+fn filter_ref_matches_filter_multisig() {
+    let members = [keypair(), keypair(), keypair()];
+    let key = multisig_key(&members, 2);
+    let key_manifest = PublicKeyManifest {
+        public_keys: members
+            .iter()
+            .map(|member| member.public_key().clone().into())
+            .collect(),
+        required: 2,
+        policy: vec![],
+    };
+    assert_eq!(key_manifest.public_key().unwrap(), key);
+
+    let mut filter = Filter::new(1, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+    let msg = filter.signed_message().unwrap();
+    let signature = multisig_sign(&msg, &members, 2);
+    let (keys, entry) = signature.split_at(members.len() * 33);
+    let second = keys
+        .chunks(33)
+        .position(|key| key == members[1].public_key().to_vec())
+        .unwrap();
+    let with_entry = |index: usize, key_signature: &[u8]| {
+        let mut signature = signature.clone();
+        push_entry(&mut signature, index as u8, key_signature);
+        signature
+    };
+
+    let cases = [
+        (signature.clone(), false),
+        // the same member signature repeated
+        (with_entry(entry[0] as usize, &entry[2..]), false),
+        // member indices past the member keys
+        (with_entry(members.len(), &entry[2..]), false),
+        (with_entry(u8::MAX as usize, &entry[2..]), false),
+        (with_entry(second, &members[1].sign(&msg).unwrap()), true),
+    ];
+    for (signature, valid) in cases {
+        filter.signature = signature;
+        let bytes = filter.to_bytes().unwrap();
+        let filter_ref = FilterRef::from_bytes(&bytes).unwrap();
+        assert_eq!(filter_ref.verify(&key.to_vec()).is_ok(), valid);
+        assert_eq!(filter.verify(&key).is_ok(), valid);
+        assert_eq!(filter.verify_with_policy(&key_manifest).is_ok(), valid);
+    }
+}
+
+#[test]
+fn filter_view_matches_filter() {
+    let hotspots = (0..200).map(|_| keypair()).collect::<Vec<_>>();
+    let others = (0..200).map(|_| keypair()).collect::<Vec<_>>();
+    let signer = keypair();
+    let dir = temp_dir("view");
+
+    for kind in [FilterKind::Xor, FilterKind::BinaryFuse] {
+        let filter = signed_filter(kind, &hotspots[..180], &hotspots[180..], &signer);
+        let path = dir.join(format!("{kind}.bin"));
+        fs::write(&path, filter.to_bytes().unwrap()).unwrap();
+        assert!(FilterView::open(&path, keypair().public_key()).is_err());
+        let view = FilterView::open(&path, signer.public_key()).unwrap();
+        assert_eq!(view.serial(), filter.serial);
+        assert_eq!(view.hash(), filter.hash().unwrap());
+        assert_eq!(view.len(), filter.len());
+
+        for hotspot in hotspots.iter().chain(&others) {
+            let key = binary(hotspot);
+            assert_eq!(view.contains(&key), filter.contains(&key));
+        }
+        for pair in hotspots[180..].chunks(2).chain(others.chunks(2)) {
+            let (a, b) = (binary(&pair[0]), binary(&pair[1]));
+            assert_eq!(view.contains_edge(&a, &b), filter.contains_edge(&a, &b));
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn filter_view_rejects_truncated_files() {
+    let hotspots = (0..20).map(|_| keypair()).collect::<Vec<_>>();
+    let signer = keypair();
+    let dir = temp_dir("truncated");
+    let path = dir.join("filter.bin");
+    let bytes = signed_filter(FilterKind::Xor, &hotspots, &[], &signer)
+        .to_bytes()
+        .unwrap();
+    for len in [0, 1, bytes.len() / 2, bytes.len() - 1] {
+        fs::write(&path, &bytes[..len]).unwrap();
+        assert!(FilterView::open_unverified(&path).is_err());
+        assert!(FilterView::open(&path, signer.public_key()).is_err());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,131 @@
+use helium_crypto::{Keypair, Sign};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use xorf_generator::{xorf::Xor32, Filter, FilterWatcher};
+
+mod common;
+use common::keypair;
+
+fn filter_bytes(keypair: &Keypair, serial: u32) -> Vec<u8> {
+    let mut filter = Filter::new(serial, Xor32::from(&[1u64, 2, 3][..])).unwrap();
+    filter.signature = keypair.sign(&filter.signed_message().unwrap()).unwrap();
+    filter.to_bytes().unwrap()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "xorf-generator-watcher-{name}-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn wait_for_serial(watcher: &FilterWatcher, serial: u32) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        if watcher.current().serial == serial {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+type Rejected = Arc<Mutex<Vec<PathBuf>>>;
+
+fn start(dir: &Path, keypair: &Keypair) -> (FilterWatcher, Rejected) {
+    let rejected = Rejected::default();
+    let on_reject = rejected.clone();
+    let watcher = FilterWatcher::builder(dir, keypair.public_key().clone())
+        .interval(Duration::from_millis(20))
+        .on_reject(move |path, _| on_reject.lock().unwrap().push(path.to_path_buf()))
+        .start()
+        .unwrap();
+    (watcher, rejected)
+}
+
+#[test]
+fn older_filters_are_not_rejected_on_start() {
+    let dir = temp_dir("start");
+    let signer = keypair();
+    for serial in 1..=3 {
+        fs::write(
+            dir.join(format!("{serial}.bin")),
+            filter_bytes(&signer, serial),
+        )
+        .unwrap();
+    }
+    let (watcher, rejected) = start(&dir, &signer);
+    assert_eq!(watcher.current().serial, 3);
+    assert!(rejected.lock().unwrap().is_empty());
+
+    // An older filter that shows up later is rejected
+    fs::write(dir.join("0.bin"), filter_bytes(&signer, 0)).unwrap();
+    let start = Instant::now();
+    while rejected.lock().unwrap().is_empty() && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(*rejected.lock().unwrap(), vec![dir.join("0.bin")]);
+    assert_eq!(watcher.current().serial, 3);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn same_size_rewrite_is_detected() {
+    let dir = temp_dir("rewrite");
+    let signer = keypair();
+    let path = dir.join("filter.bin");
+    fs::write(&path, filter_bytes(&signer, 1)).unwrap();
+    let (watcher, rejected) = start(&path, &signer);
+    assert_eq!(watcher.current().serial, 1);
+
+    // Rewriting the file in place keeps its size and inode, and within the
+    // resolution of the file system its modification time
+    let data = filter_bytes(&signer, 2);
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!(data.len(), metadata.len() as usize);
+    fs::write(&path, data).unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(metadata.modified().unwrap())
+        .unwrap();
+    assert!(wait_for_serial(&watcher, 2));
+    assert!(rejected.lock().unwrap().is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn panic_is_reported_and_watching_continues() {
+    let dir = temp_dir("panic");
+    let signer = keypair();
+    fs::write(dir.join("1.bin"), filter_bytes(&signer, 1)).unwrap();
+    let rejected = Rejected::default();
+    let on_reject = rejected.clone();
+    let watcher = FilterWatcher::builder(&dir, signer.public_key().clone())
+        .interval(Duration::from_millis(20))
+        .on_accept(|_, filter| assert_ne!(filter.serial, 2, "accept callback failed"))
+        .on_reject(move |path, _| on_reject.lock().unwrap().push(path.to_path_buf()))
+        .start()
+        .unwrap();
+
+    fs::write(dir.join("2.bin"), filter_bytes(&signer, 2)).unwrap();
+    let start = Instant::now();
+    while rejected.lock().unwrap().is_empty() && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(*rejected.lock().unwrap(), vec![dir.clone()]);
+    assert!(watcher.is_running());
+
+    fs::write(dir.join("3.bin"), filter_bytes(&signer, 3)).unwrap();
+    assert!(wait_for_serial(&watcher, 3));
+    let _ = fs::remove_dir_all(&dir);
+}